@@ -14,6 +14,11 @@ pub struct Camera {
     pub lens_radius: f32,
     pub time0: f32,
     pub time1: f32,
+    pub lookat: Point3,
+    pub vup: Color,
+    pub vfov: f32,
+    pub aspect_ratio: f32,
+    pub focus_dist: f32,
 }
 
 impl Camera {
@@ -51,6 +56,11 @@ impl Camera {
             lens_radius: aperture / 2.0,
             time0,
             time1,
+            lookat,
+            vup,
+            vfov,
+            aspect_ratio,
+            focus_dist,
         }
     }
 
@@ -86,9 +96,29 @@ impl Camera {
             lens_radius: aperture / 2.0,
             time0: 0.0,
             time1: 0.0,
+            lookat,
+            vup,
+            vfov,
+            aspect_ratio,
+            focus_dist,
         }
     }
 
+    // The same view, framed for another image shape
+    pub fn with_aspect_ratio(&self, aspect_ratio: f32) -> Camera {
+        Camera::new(
+            self.orig,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.lens_radius * 2.0,
+            self.focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+
     pub fn get_ray(&self, u: f32, v: f32) -> Ray {
         let rd = self.lens_radius * random_in_unit_disk();
         let offset = self.u * rd.x + self.v * rd.y;
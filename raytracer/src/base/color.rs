@@ -1,31 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+
 use super::{rtweekend::clamp, vec3::Color};
-pub fn write_color(pixel_color: &mut Color, samples_per_pixel: u16, pixel: &mut image::Rgb<u8>) {
-    let mut r = pixel_color.x;
-    let mut g = pixel_color.y;
-    let mut b = pixel_color.z;
-
-    if r != r {
-        r = 0.0;
-    }
-    if g != g {
-        g = 0.0;
-    }
-    if b != b {
-        b = 0.0;
-    }
-
-    let scale = 1.0 / samples_per_pixel as f32;
-    r = 256.0 * clamp((r * scale).sqrt(), 0.0, 0.999);
-    g = 256.0 * clamp((g * scale).sqrt(), 0.0, 0.999);
-    b = 256.0 * clamp((b * scale).sqrt(), 0.0, 0.999);
-
-    *pixel = image::Rgb([r as u8, g as u8, b as u8]);
-    // unsafe {
-    //     format!(
-    //         "{} {} {}\n",
-    //         (255.999 * clamp(r, 0.0, 0.999)).to_int_unchecked::<u16>(),
-    //         (255.999 * clamp(g, 0.0, 0.999)).to_int_unchecked::<u16>(),
-    //         (255.999 * clamp(b, 0.0, 0.999)).to_int_unchecked::<u16>()
-    //     )
-    // }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    // Maps `white` (None: the brightest pixel) to 1.0
+    ExtendedReinhard { white: Option<f32> },
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "reinhard-extended" => Ok(ToneMap::ExtendedReinhard { white: None }),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!(
+                "unknown tone map `{}`, expected clamp, reinhard, reinhard-extended or aces",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ToneMap::Clamp => "clamp",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::ExtendedReinhard { .. } => "reinhard-extended",
+            ToneMap::Aces => "aces",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Srgb,
+    Gamma(f32),
+}
+
+// How linear radiance becomes an 8-bit display value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayTransform {
+    pub tone_map: ToneMap,
+    pub exposure: f32,
+    pub transfer: Transfer,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+            transfer: Transfer::Srgb,
+        }
+    }
+}
+
+impl DisplayTransform {
+    // Fills in an ExtendedReinhard white point left open from the exposed image
+    pub fn resolve(&self, pixels: &[Color]) -> DisplayTransform {
+        let mut resolved = *self;
+        if let ToneMap::ExtendedReinhard { white: None } = self.tone_map {
+            let scale = self.exposure.exp2();
+            let white = pixels
+                .iter()
+                .map(|c| luminance(&(*c * scale)))
+                .filter(|l| l.is_finite())
+                .fold(0.0, f32::max);
+            resolved.tone_map = ToneMap::ExtendedReinhard {
+                white: Some(white.max(1.0)),
+            };
+        }
+        resolved
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        let c = color * self.exposure.exp2();
+        let mapped = match self.tone_map {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => {
+                let w2 = white.unwrap_or(1.0).powi(2);
+                scale_luminance(c, |l| l * (1.0 + l / w2) / (1.0 + l))
+            }
+            ToneMap::Aces => Color::new(aces(c.x), aces(c.y), aces(c.z)),
+        };
+        let encode = |v: f32| match self.transfer {
+            Transfer::Srgb => srgb_oetf(clamp(v, 0.0, 1.0)),
+            Transfer::Gamma(gamma) => clamp(v, 0.0, 1.0).powf(1.0 / gamma),
+        };
+        Color::new(encode(mapped.x), encode(mapped.y), encode(mapped.z))
+    }
+
+    pub fn encode(&self, color: Color) -> image::Rgb<u8> {
+        let c = self.apply(color);
+        let quantize = |v: f32| (v * 255.0 + 0.5) as u8;
+        image::Rgb([quantize(c.x), quantize(c.y), quantize(c.z)])
+    }
+}
+
+pub fn luminance(c: &Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn scale_luminance<F: Fn(f32) -> f32>(c: Color, curve: F) -> Color {
+    let l = luminance(&c);
+    if l <= 0.0 {
+        return Color::zero();
+    }
+    c * (curve(l) / l)
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: f32) -> f32 {
+    let x = x.max(0.0);
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+pub fn srgb_oetf(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Linear sRGB colour of a black body at `kelvin`, scaled to unit luminance.
+// Planck's law is integrated against Wyman, Sloan and Shirley's fit of the
+// CIE 1931 matching functions
+pub fn blackbody(kelvin: f32) -> Color {
+    let lobe = |lambda: f64, mu: f64, low: f64, high: f64| {
+        let sigma = if lambda < mu { low } else { high };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+    let (h, c, k) = (6.626_070_15e-34, 2.997_924_58e8, 1.380_649e-23);
+    let t = kelvin.max(1.0) as f64;
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for step in 0..=80 {
+        let nm = 380.0 + 5.0 * step as f64;
+        let lambda = nm * 1e-9;
+        let radiance = 2.0 * h * c * c / lambda.powi(5) / ((h * c / (lambda * k * t)).exp() - 1.0);
+        x += radiance
+            * (1.056 * lobe(nm, 599.8, 37.9, 31.0) + 0.362 * lobe(nm, 442.0, 16.0, 26.7)
+                - 0.065 * lobe(nm, 501.1, 20.4, 26.2));
+        y += radiance * (0.821 * lobe(nm, 568.8, 46.9, 40.5) + 0.286 * lobe(nm, 530.9, 16.3, 31.1));
+        z += radiance * (1.217 * lobe(nm, 437.0, 11.8, 36.0) + 0.681 * lobe(nm, 459.0, 26.0, 13.8));
+    }
+    if y <= 0.0 {
+        return Color::zero();
+    }
+    let (x, z) = ((x / y) as f32, (z / y) as f32);
+    let rgb = Color::new(
+        (3.2406 * x - 1.5372 - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 + 1.0570 * z).max(0.0),
+    );
+    rgb / luminance(&rgb)
+}
+
+// Inverse of `srgb_oetf`, for decoding 8-bit images into linear radiance
+pub fn srgb_eotf(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_transform() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4614).abs() < 1e-4);
+        assert!((srgb_oetf(srgb_eotf(0.5)) - 0.5).abs() < 1e-5);
+
+        let display = DisplayTransform::default();
+        assert_eq!(display.encode(Color::new(15.0, 0.5, -1.0)).0, [255, 188, 0]);
+
+        let bright = [Color::new(8.0, 8.0, 8.0), Color::new(0.5, 0.5, 0.5)];
+        let reinhard = DisplayTransform {
+            tone_map: ToneMap::ExtendedReinhard { white: None },
+            exposure: -1.0,
+            transfer: Transfer::Gamma(1.0),
+        }
+        .resolve(&bright);
+        assert_eq!(
+            reinhard.tone_map,
+            ToneMap::ExtendedReinhard { white: Some(4.0) }
+        );
+        assert!((reinhard.apply(bright[0]).x - 1.0).abs() < 1e-5);
+
+        let aces = DisplayTransform {
+            tone_map: ToneMap::Aces,
+            ..DisplayTransform::default()
+        };
+        let (a, b) = (aces.apply(Color::ones()), aces.apply(Color::ones() * 4.0));
+        assert!(a.x < b.x && b.x < 1.0);
+        assert_eq!(
+            "reinhard-extended".parse(),
+            Ok(ToneMap::ExtendedReinhard { white: None })
+        );
+    }
+
+    #[test]
+    fn test_blackbody() {
+        // D65 is close to 6500 K; a candle is orange and a blue sky bluish
+        let daylight = blackbody(6500.0);
+        assert!((luminance(&daylight) - 1.0).abs() < 1e-4);
+        assert!((daylight.x - 1.0).abs() < 0.1 && (daylight.z - 1.0).abs() < 0.15);
+        let candle = blackbody(1900.0);
+        assert!(candle.x > candle.y && candle.y > candle.z);
+        let sky = blackbody(12000.0);
+        assert!(sky.z > sky.x);
+    }
 }
@@ -0,0 +1,30 @@
+use serde_json::{json, Value};
+
+use super::{Mat4, Vec3};
+
+pub fn number(x: f32) -> Value {
+    if x.fract() == 0.0 && x.abs() < 1e9 {
+        return Value::from(x as i64);
+    }
+    // Go through the shortest decimal form so 0.1f32 is written as 0.1
+    x.to_string()
+        .parse::<f64>()
+        .map(Value::from)
+        .unwrap_or(Value::Null)
+}
+
+pub fn vec3_value(v: &Vec3) -> Value {
+    json!({
+        "x": number(v.x),
+        "y": number(v.y),
+        "z": number(v.z),
+    })
+}
+
+pub fn matrix_value(m: &Mat4) -> Value {
+    Value::Array(
+        m.m.iter()
+            .map(|row| Value::Array(row.iter().map(|&x| number(x)).collect()))
+            .collect(),
+    )
+}
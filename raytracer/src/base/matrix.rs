@@ -0,0 +1,177 @@
+use super::rtweekend::degrees_to_radians;
+use super::vec3::{Point3, Vec3};
+use std::ops::Mul;
+
+// Row-major affine transform acting on column vectors, so `a * b` applies b first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::scale(Vec3::ones())
+    }
+
+    pub fn translate(offset: Vec3) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][3] = offset[i];
+        }
+        t
+    }
+
+    pub fn scale(s: Vec3) -> Mat4 {
+        let mut t = Mat4 { m: [[0.0; 4]; 4] };
+        for i in 0..3 {
+            t.m[i][i] = s[i];
+        }
+        t.m[3][3] = 1.0;
+        t
+    }
+
+    // Counter-clockwise by `angle` degrees looking down the axis towards the origin
+    pub fn rotate(axis: Vec3, angle: f32) -> Mat4 {
+        let a = axis.unit();
+        let radians = degrees_to_radians(angle);
+        let (sin, cos) = (radians.sin(), radians.cos());
+        let k = 1.0 - cos;
+        Mat4::new([
+            [
+                a.x * a.x * k + cos,
+                a.x * a.y * k - a.z * sin,
+                a.x * a.z * k + a.y * sin,
+                0.0,
+            ],
+            [
+                a.y * a.x * k + a.z * sin,
+                a.y * a.y * k + cos,
+                a.y * a.z * k - a.x * sin,
+                0.0,
+            ],
+            [
+                a.z * a.x * k - a.y * sin,
+                a.z * a.y * k + a.x * sin,
+                a.z * a.z * k + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = Mat4 { m: [[0.0; 4]; 4] };
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = self.m[j][i];
+            }
+        }
+        t
+    }
+
+    // Gauss-Jordan elimination with partial pivoting; None for a singular matrix
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        inv[i][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4::new(inv))
+    }
+
+    // Determinant of the upper-left 3x3 block, the linear part
+    pub fn determinant3(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut t = Mat4 { m: [[0.0; 4]; 4] };
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let rotate = Mat4::rotate(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        assert_close(
+            rotate.transform_vector(&Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+
+        let m = Mat4::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotate(Vec3::new(1.0, 1.0, 0.0), 33.0)
+            * Mat4::scale(Vec3::new(2.0, 0.5, 3.0));
+        let p = Point3::new(-0.3, 0.7, 1.1);
+        let q = m.transform_point(&p);
+        let inv = m.inverse().unwrap();
+        assert_close(inv.transform_point(&q), p);
+        assert!((m.determinant3() - 3.0).abs() < 1e-4);
+
+        let product = m * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.m[i][j] - expected).abs() < 1e-5);
+            }
+        }
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod camera;
 pub mod color;
+pub mod document;
+pub mod matrix;
 pub mod onb;
 pub mod ray;
 pub mod rtweekend;
@@ -7,8 +9,8 @@ pub mod vec3;
 
 pub use self::camera::*;
 pub use self::color::*;
+pub use self::matrix::*;
 pub use self::ray::*;
 pub use self::rtweekend::*;
 pub use self::vec3::*;
 pub use core::panic;
-pub use rand::{thread_rng, Rng};
@@ -34,6 +34,15 @@ impl Onb {
         a.x * self.u() + a.y * self.v() + a.z * self.w()
     }
 
+    // Coordinates of a world-space vector in this basis
+    pub fn to_local(&self, a: &Color) -> Color {
+        Color::new(
+            Color::dot(*a, self.u()),
+            Color::dot(*a, self.v()),
+            Color::dot(*a, self.w()),
+        )
+    }
+
     pub fn local_single(&self, a: f32, b: f32, c: f32) -> Color {
         a * self.u() + b * self.v() + c * self.w()
     }
@@ -1,7 +1,64 @@
-use rand::{thread_rng, Rng};
+use std::cell::RefCell;
 
 use super::Color;
 
+// PCG32 (XSH RR), small enough to reseed for every camera sample
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(init_state: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(init_state);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // Uniform in [0, 1), built from the top 24 bits so it never rounds up to 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / 16_777_216.0)
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+thread_local! {
+    static RNG: RefCell<Pcg32> = RefCell::new(Pcg32::new(0, 0));
+}
+
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::new(splitmix64(seed), 0));
+}
+
+// Every draw made while tracing a sample comes from this sequence, so a pixel
+// renders the same no matter which thread picks it up
+pub fn seed_sample(seed: u64, pixel: u64, sample: u64) {
+    let state = splitmix64(seed ^ splitmix64(sample));
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::new(state, pixel));
+}
+
 pub const INF: f32 = f32::INFINITY;
 pub const PI: f32 = 3.1415926535897932385;
 
@@ -10,9 +67,7 @@ pub fn degrees_to_radians(degrees: f32) -> f32 {
 }
 
 pub fn random_double(min: Option<f32>, max: Option<f32>) -> f32 {
-    let mut rng = thread_rng();
-    let n: f32 = rng.gen_range(min.unwrap_or(0.0), max.unwrap_or(1.0));
-    n
+    random_f_m(min.unwrap_or(0.0), max.unwrap_or(1.0))
 }
 
 pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
@@ -26,19 +81,27 @@ pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
 }
 
 pub fn random_f() -> f32 {
-    thread_rng().gen_range(0.0, 1.0) //thread_rng().gen::<f32>()
+    RNG.with(|rng| rng.borrow_mut().next_f32())
 }
 
 pub fn random_u() -> u16 {
-    thread_rng().gen::<u16>()
+    RNG.with(|rng| (rng.borrow_mut().next_u32() >> 16) as u16)
 }
 
 pub fn random_u_m(min: u16, max: u16) -> u16 {
-    thread_rng().gen_range(min, max)
+    let range = (max - min) as u64;
+    let r = RNG.with(|rng| rng.borrow_mut().next_u32()) as u64;
+    min + ((r * range) >> 32) as u16
+}
+
+// Uniform index into a collection of `len` items, however many there are
+pub fn random_index(len: usize) -> usize {
+    let r = RNG.with(|rng| rng.borrow_mut().next_u32()) as u128;
+    ((r * len as u128) >> 32) as usize
 }
 
 pub fn random_f_m(min: f32, max: f32) -> f32 {
-    thread_rng().gen_range(min, max)
+    min + (max - min) * random_f()
 }
 
 pub fn random_cosine_direction() -> Color {
@@ -66,3 +129,17 @@ pub fn fmax(a: f32, b: f32) -> f32 {
     }
     b
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_index_past_u16() {
+        seed_rng(3);
+        let len = 70000;
+        let indices: Vec<usize> = (0..1000).map(|_| random_index(len)).collect();
+        assert!(indices.iter().all(|&i| i < len));
+        assert!(indices.iter().any(|&i| i > u16::MAX as usize));
+    }
+}
@@ -24,6 +24,15 @@ impl AABB {
         self.maximum
     }
 
+    pub fn surface_area(&self) -> f32 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.minimum + self.maximum) * 0.5
+    }
+
     pub fn hit(&self, ray: &Ray, t_mini: f32, t_maxi: f32) -> bool {
         let mut t_min = t_mini;
         let mut t_max = t_maxi;
@@ -1,156 +1,441 @@
 use super::aabb::*;
 use crate::base::{ray::*, rtweekend::*, vec3::*};
 use crate::hit::{hittable::*, hittable_list::*};
-use crate::objects::texture::SolidColor;
-use crate::objects::{material::Lambertian, sphere::Sphere};
-use std::cmp::Ordering;
+use serde_json::{json, Value};
+use std::fmt;
 use std::sync::Arc;
 
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const MAX_DEPTH: usize = 64;
+// Cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f32 = 0.125;
+
 #[derive(Clone)]
-pub struct BvhNode {
-    pub left: Arc<dyn Hittable>,
-    pub right: Arc<dyn Hittable>,
-    pub boox: AABB,
+struct LinearNode {
+    bounds: AABB,
+    // First entry in `indices` for leaves, index of the second child for interior nodes
+    offset: usize,
+    count: usize,
+    axis: usize,
 }
-impl Hittable for BvhNode {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if !self.boox.hit(ray, t_min, t_max) {
-            return None;
+
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    pub primitives: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+    pub max_leaf_size: usize,
+    pub sah_cost: f32,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BVH: {} primitives, {} nodes ({} leaves, at most {} per leaf), depth {}, SAH cost {:.2}",
+            self.primitives,
+            self.nodes,
+            self.leaves,
+            self.max_leaf_size,
+            self.depth,
+            self.sah_cost
+        )
+    }
+}
+
+struct BuildItem {
+    index: usize,
+    bounds: AABB,
+    centroid: Point3,
+}
+
+#[derive(Clone)]
+pub struct Bvh {
+    objects: Vec<Arc<dyn Hittable>>,
+    indices: Vec<usize>,
+    nodes: Vec<LinearNode>,
+    // Objects without a bounding box are tested on every ray
+    unbounded: Vec<usize>,
+    stats: BvhStats,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Arc<dyn Hittable>>, time0: f32, time1: f32) -> Bvh {
+        let mut items = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            match object.bounding_box(time0, time1) {
+                Some(bounds) => items.push(BuildItem {
+                    index,
+                    centroid: bounds.centroid(),
+                    bounds,
+                }),
+                None => unbounded.push(index),
+            }
         }
 
-        let mut opt = None;
-        let mut t = t_max;
+        let mut bvh = Bvh {
+            objects,
+            indices: Vec::with_capacity(items.len()),
+            nodes: Vec::with_capacity(2 * items.len()),
+            unbounded,
+            stats: BvhStats::default(),
+        };
+        if !items.is_empty() {
+            bvh.build(&mut items, 1);
+        }
+        bvh.stats = bvh.compute_stats();
+        bvh
+    }
 
-        if let Some(hitleft) = self.left.hit(ray, t_min, t_max) {
-            t = hitleft.t;
-            opt = Some(hitleft);
+    pub fn new_with_list(list: &HittableList, time0: f32, time1: f32) -> Bvh {
+        Bvh::new(list.objects.clone(), time0, time1)
+    }
+
+    pub fn stats(&self) -> &BvhStats {
+        &self.stats
+    }
+
+    // Calls `f` with the index, in the order given to `new`, of every object
+    // whose box the ray crosses, not just the nearest one
+    pub fn for_each_candidate(&self, ray: &Ray, t_min: f32, t_max: f32, mut f: impl FnMut(usize)) {
+        for &i in &self.unbounded {
+            f(i);
+        }
+        if self.nodes.is_empty() {
+            return;
         }
-        if let Some(hitright) = self.right.hit(ray, t_min, t) {
-            opt = Some(hitright);
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut top = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bounds.hit(ray, t_min, t_max) {
+                if node.count > 0 {
+                    for &i in &self.indices[node.offset..node.offset + node.count] {
+                        f(i);
+                    }
+                } else {
+                    stack[top] = node.offset;
+                    top += 1;
+                    current += 1;
+                    continue;
+                }
+            }
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            current = stack[top];
         }
-        opt
     }
 
-    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
-        Some(self.boox.clone())
+    fn build(&mut self, items: &mut [BuildItem], depth: usize) -> usize {
+        let bounds = enclose(items.iter().map(|item| &item.bounds));
+        let node = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bounds: bounds.clone(),
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        match self.split(items, &bounds, depth) {
+            Some((mid, axis)) => {
+                let (left, right) = items.split_at_mut(mid);
+                self.build(left, depth + 1);
+                let second = self.build(right, depth + 1);
+                self.nodes[node].offset = second;
+                self.nodes[node].axis = axis;
+            }
+            None => {
+                self.nodes[node].offset = self.indices.len();
+                self.nodes[node].count = items.len();
+                self.indices.extend(items.iter().map(|item| item.index));
+            }
+        }
+        self.stats.depth = self.stats.depth.max(depth);
+        node
     }
-}
 
-impl BvhNode {
-    pub fn new_with_list(list: &mut HittableList, time0: f32, time1: f32) -> BvhNode {
-        let len = list.objects.len();
-        BvhNode::new(&mut list.objects, 0, len, time0, time1)
-    }
-
-    pub fn new(
-        src_objects: &mut Vec<Arc<dyn Hittable>>,
-        start: usize,
-        end: usize,
-        time0: f32,
-        time1: f32,
-    ) -> BvhNode {
-        let mut bvh_node = BvhNode {
-            left: Arc::new(Sphere::new(
-                &Point3::zero(),
-                0.0,
-                Lambertian::new(SolidColor::new_with_color(Color::zero())),
-            )),
-            right: Arc::new(Sphere::new(
-                &Point3::zero(),
-                0.0,
-                Lambertian::new(SolidColor::new_with_color(Color::zero())),
-            )),
-            boox: AABB::new(&Color::zero(), &Color::zero()),
+    // Binned SAH split; None means the items are cheaper to keep in one leaf
+    fn split(
+        &self,
+        items: &mut [BuildItem],
+        bounds: &AABB,
+        depth: usize,
+    ) -> Option<(usize, usize)> {
+        let n = items.len();
+        if n == 1 || depth >= MAX_DEPTH {
+            return None;
+        }
+        let centroids = enclose_points(items.iter().map(|item| &item.centroid));
+        let extent = centroids.maximum - centroids.minimum;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
         };
+        if extent[axis] <= 0.0 {
+            return None;
+        }
 
-        let axis = random_u_m(0, 3);
-        let object_span = end - start;
-        let comparator = match axis {
-            0 => BvhNode::box_x_compare,
-            1 => BvhNode::box_y_compare,
-            2 => BvhNode::box_z_compare,
-            _ => panic!("AXIS ERROR"),
-        };
-        match object_span {
-            1 => {
-                bvh_node.left = src_objects[start].clone();
-                bvh_node.right = src_objects[start].clone();
+        let lo = centroids.minimum[axis];
+        let scale = BINS as f32 / extent[axis];
+        let bin_of =
+            |item: &BuildItem| (((item.centroid[axis] - lo) * scale) as usize).min(BINS - 1);
+
+        let mut counts = [0usize; BINS];
+        let mut bin_bounds: Vec<Option<AABB>> = vec![None; BINS];
+        for item in items.iter() {
+            let b = bin_of(item);
+            counts[b] += 1;
+            bin_bounds[b] = Some(match &bin_bounds[b] {
+                Some(bb) => surrounding_box(bb, &item.bounds),
+                None => item.bounds.clone(),
+            });
+        }
+
+        // Sweep from the right so each split's right-hand area is known
+        let mut right_area = [0.0; BINS];
+        let mut right_count = [0usize; BINS];
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+        for b in (1..BINS).rev() {
+            acc = merge(acc, &bin_bounds[b]);
+            count += counts[b];
+            right_area[b] = acc.as_ref().map_or(0.0, AABB::surface_area);
+            right_count[b] = count;
+        }
+
+        let total_area = bounds.surface_area();
+        let mut best = (f32::INFINITY, 0);
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+        for b in 0..BINS - 1 {
+            acc = merge(acc, &bin_bounds[b]);
+            count += counts[b];
+            if count == 0 || right_count[b + 1] == 0 {
+                continue;
             }
-            2 => {
-                if comparator(&src_objects[start], &src_objects[start + 1]) == Ordering::Less {
-                    bvh_node.right = src_objects[start + 1].clone();
-                    bvh_node.left = src_objects[start].clone();
-                } else {
-                    bvh_node.left = src_objects[start + 1].clone();
-                    bvh_node.right = src_objects[start].clone();
-                }
+            let left_area = acc.as_ref().map_or(0.0, AABB::surface_area);
+            let cost = TRAVERSAL_COST
+                + (count as f32 * left_area + right_count[b + 1] as f32 * right_area[b + 1])
+                    / total_area;
+            if cost < best.0 {
+                best = (cost, b);
             }
-            _ => {
-                let objects = &mut src_objects[start..end];
-                objects.sort_by(|a, b| comparator(a, b));
+        }
+
+        if n <= MAX_LEAF_SIZE && best.0 >= n as f32 {
+            return None;
+        }
+        if best.0.is_infinite() {
+            // Every centroid fell into one bin; fall back to a median split
+            let mid = n / 2;
+            items.select_nth_unstable_by(mid, |a, b| {
+                a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap()
+            });
+            return Some((mid, axis));
+        }
 
-                let mid = (start + end) / 2;
-                bvh_node.left = Arc::new(BvhNode::new(src_objects, start, mid, time0, time1));
-                bvh_node.right = Arc::new(BvhNode::new(src_objects, mid, end, time0, time1));
+        let mut mid = 0;
+        for i in 0..n {
+            if bin_of(&items[i]) <= best.1 {
+                items.swap(i, mid);
+                mid += 1;
             }
         }
-        if let Some(box0) = bvh_node.left.bounding_box(time0, time1) {
-            if let Some(box1) = bvh_node.right.bounding_box(time0, time1) {
-                bvh_node.boox = surrounding_box(&box0, &box1);
-                return bvh_node;
+        Some((mid, axis))
+    }
+
+    fn compute_stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            primitives: self.objects.len(),
+            nodes: self.nodes.len(),
+            depth: self.stats.depth,
+            ..BvhStats::default()
+        };
+        let root_area = match self.nodes.first() {
+            Some(root) if root.bounds.surface_area() > 0.0 => root.bounds.surface_area(),
+            _ => return stats,
+        };
+        for node in &self.nodes {
+            let weight = node.bounds.surface_area() / root_area;
+            if node.count > 0 {
+                stats.leaves += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(node.count);
+                stats.sah_cost += node.count as f32 * weight;
+            } else {
+                stats.sah_cost += TRAVERSAL_COST * weight;
             }
         }
+        stats
+    }
+}
 
-        panic!("NO BOUNDING BOX IN BVHNODE::NEW");
+fn enclose<'a>(mut boxes: impl Iterator<Item = &'a AABB>) -> AABB {
+    let first = boxes.next().unwrap().clone();
+    boxes.fold(first, |acc, b| surrounding_box(&acc, b))
+}
+
+fn enclose_points<'a>(points: impl Iterator<Item = &'a Point3>) -> AABB {
+    let mut bounds = AABB::new(&Point3::new(INF, INF, INF), &Point3::new(-INF, -INF, -INF));
+    for p in points {
+        bounds = surrounding_box(&bounds, &AABB::new(p, p));
     }
+    bounds
+}
 
-    fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: u16) -> Ordering {
-        let box_a = a.bounding_box(0.0, 0.0);
-        let box_b = b.bounding_box(0.0, 0.0);
+fn merge(acc: Option<AABB>, b: &Option<AABB>) -> Option<AABB> {
+    match (acc, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(&a, b)),
+        (a, None) => a,
+        (None, b) => b.clone(),
+    }
+}
 
-        match axis {
-            0 => {
-                if let Some(rec1) = box_a {
-                    if let Some(rec2) = box_b {
-                        if let Some(cmp) = rec1.minimum.x.partial_cmp(&rec2.minimum.x) {
-                            return cmp;
-                        }
-                    }
-                }
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut closest = t_max;
+        let mut result = None;
+        for &i in &self.unbounded {
+            if let Some(rec) = self.objects[i].hit(ray, t_min, closest) {
+                closest = rec.t;
+                result = Some(rec);
             }
-            1 => {
-                if let Some(rec1) = box_a {
-                    if let Some(rec2) = box_b {
-                        if let Some(cmp) = rec1.minimum.y.partial_cmp(&rec2.minimum.y) {
-                            return cmp;
+        }
+        if self.nodes.is_empty() {
+            return result;
+        }
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut top = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bounds.hit(ray, t_min, closest) {
+                if node.count > 0 {
+                    for &i in &self.indices[node.offset..node.offset + node.count] {
+                        if let Some(rec) = self.objects[i].hit(ray, t_min, closest) {
+                            closest = rec.t;
+                            result = Some(rec);
                         }
                     }
-                }
-            }
-            2 => {
-                if let Some(rec1) = box_a {
-                    if let Some(rec2) = box_b {
-                        if let Some(cmp) = rec1.minimum.z.partial_cmp(&rec2.minimum.z) {
-                            return cmp;
-                        }
+                } else {
+                    // Visit the child nearer to the ray origin first
+                    if ray.dir[node.axis] < 0.0 {
+                        stack[top] = current + 1;
+                        current = node.offset;
+                    } else {
+                        stack[top] = node.offset;
+                        current += 1;
                     }
+                    top += 1;
+                    continue;
                 }
             }
-            _ => {}
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            current = stack[top];
         }
+        result
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|root| root.bounds.clone())
+    }
 
-        panic!("No bounding box in bvh_node constructor.")
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        let weight = 1.0 / self.objects.len() as f32;
+        self.objects
+            .iter()
+            .map(|object| object.pdf_value(o, v, time) * weight)
+            .sum()
     }
 
-    fn box_x_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Ordering {
-        BvhNode::box_compare(a, b, 0)
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        let index = random_index(self.objects.len());
+        self.objects[index].random(o, time)
     }
 
-    fn box_y_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Ordering {
-        BvhNode::box_compare(a, b, 1)
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for object in &self.objects {
+            gather_lights(object, lights);
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut items = Vec::new();
+        for object in &self.objects {
+            items.push(object.to_document()?);
+        }
+        Some(json!({
+            "type": "BVH",
+            "items": items,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::material::Lambertian;
+    use crate::objects::sphere::Sphere;
+    use crate::objects::texture::SolidColor;
+
+    #[test]
+    fn test_matches_linear_list() {
+        seed_rng(7);
+        let mut list = HittableList::new();
+        for _ in 0..300 {
+            list.add(Arc::new(Sphere::new(
+                &Vec3::random(-20.0, 20.0),
+                random_f_m(0.1, 2.0),
+                Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+            )));
+        }
+        let bvh = Bvh::new_with_list(&list, 0.0, 1.0);
+        let stats = bvh.stats();
+        assert_eq!(stats.primitives, 300);
+        assert!(stats.max_leaf_size <= MAX_LEAF_SIZE && stats.depth < 30);
+        assert!(stats.sah_cost < 300.0);
+
+        for _ in 0..2000 {
+            let ray = Ray::new(&Vec3::random(-25.0, 25.0), &Vec3::random(-1.0, 1.0), 0.0);
+            let expected = list.hit(&ray, 0.001, INF).map(|rec| rec.t);
+            assert_eq!(bvh.hit(&ray, 0.001, INF).map(|rec| rec.t), expected);
+        }
     }
 
-    fn box_z_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Ordering {
-        BvhNode::box_compare(a, b, 2)
+    #[test]
+    fn test_random_reaches_every_object() {
+        // Sphere i sits at x = i, seen from one unit behind the start of the row
+        let objects: Vec<Arc<dyn Hittable>> = (0..70000)
+            .map(|i| {
+                Arc::new(Sphere::new(
+                    &Point3::new(i as f32, 0.0, 0.0),
+                    0.1,
+                    Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+                )) as Arc<dyn Hittable>
+            })
+            .collect();
+        let bvh = Bvh::new(objects, 0.0, 1.0);
+        seed_rng(3);
+        let o = Point3::new(0.0, 0.0, -1.0);
+        let beyond_u16 = (0..1000)
+            .map(|_| bvh.random(&o, 0.0))
+            .filter(|v| v.x / v.z > u16::MAX as f32 + 0.5)
+            .count();
+        assert!(beyond_u16 > 0);
     }
 }
@@ -0,0 +1,269 @@
+use clap::{value_t, App, Arg, ArgMatches};
+use std::ffi::OsString;
+
+use crate::base::color::{DisplayTransform, ToneMap, Transfer};
+
+pub struct Options {
+    pub scene: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples_per_pixel: u16,
+    pub pass_spp: Option<u32>,
+    pub adaptive_threshold: Option<f32>,
+    pub min_spp: u32,
+    pub sample_map: Option<String>,
+    pub max_depth: u16,
+    pub threads: usize,
+    pub tile_size: u32,
+    pub outputs: Vec<String>,
+    pub seed: u64,
+    pub display: DisplayTransform,
+    pub export: Option<String>,
+    pub checkpoint: Option<String>,
+    pub resume: Option<String>,
+    pub list_scenes: bool,
+}
+
+const DEFAULT_WIDTH: u32 = 40;
+
+impl Options {
+    pub fn parse() -> Options {
+        Options::parse_from(std::env::args_os())
+    }
+
+    pub fn parse_from<I, T>(args: I) -> Options
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = App::new("raytracer")
+            .version(env!("CARGO_PKG_VERSION"))
+            .about("Renders a built-in scene or a JSON/YAML scene file")
+            .arg(
+                Arg::with_name("scene")
+                    .help("Built-in scene name or path to a .json/.yaml scene file")
+                    .default_value("pic"),
+            )
+            .arg(
+                number_arg(
+                    "width",
+                    "Image width in pixels [default: height * aspect ratio, or 40]",
+                )
+                .validator(image_side),
+            )
+            .arg(
+                number_arg(
+                    "height",
+                    "Image height in pixels [default: width / aspect ratio]",
+                )
+                .validator(image_side),
+            )
+            .arg(number_arg("spp", "Samples per pixel").default_value("100"))
+            .arg(number_arg(
+                "pass-spp",
+                "Render progressively in passes of N samples per pixel, rewriting the outputs after each",
+            ))
+            .arg(number_arg(
+                "adaptive",
+                "Stop sampling a pixel once the standard error of its mean luminance falls below this \
+                 fraction of the mean; --spp becomes the per-pixel maximum",
+            ))
+            .arg(
+                number_arg("min-spp", "Samples every pixel gets before adaptive sampling may stop it")
+                    .default_value("16"),
+            )
+            .arg(
+                Arg::with_name("sample-map")
+                    .long("sample-map")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Writes the number of samples each pixel received, scaled so --spp is white"),
+            )
+            .arg(number_arg("max-depth", "Maximum ray bounce depth").default_value("10"))
+            .arg(number_arg(
+                "threads",
+                "Number of worker threads [default: number of CPU cores]",
+            ))
+            .arg(number_arg("tile-size", "Edge length in pixels of the square tiles handed to threads").default_value("16"))
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("FILE")
+                    .default_value("output/pic.png")
+                    .help(
+                        "Output image path, may be repeated; .exr, .hdr and .pfm keep linear radiance",
+                    ),
+            )
+            .arg(number_arg("seed", "Seed for the random number generator").default_value("0"))
+            .arg(
+                Arg::with_name("tonemap")
+                    .long("tonemap")
+                    .takes_value(true)
+                    .value_name("OPERATOR")
+                    .possible_values(&["clamp", "reinhard", "reinhard-extended", "aces"])
+                    .default_value("clamp")
+                    .help("Tone mapping applied to 8-bit outputs"),
+            )
+            .arg(
+                number_arg("exposure", "Exposure adjustment in stops (EV)")
+                    .allow_hyphen_values(true)
+                    .default_value("0"),
+            )
+            .arg(number_arg(
+                "white",
+                "Radiance mapped to white by reinhard-extended [default: brightest pixel]",
+            ))
+            .arg(number_arg(
+                "gamma",
+                "Encode 8-bit outputs with a plain power curve instead of the sRGB transfer function",
+            ))
+            .arg(
+                Arg::with_name("export")
+                    .long("export")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Writes the scene to a .json/.yaml file instead of rendering it"),
+            )
+            .arg(
+                Arg::with_name("checkpoint")
+                    .long("checkpoint")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Saves the accumulated radiance and sample counts after every pass"),
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help(
+                        "Continues a checkpointed render up to --spp samples per pixel; the scene, \
+                         size, depth and seed come from the checkpoint",
+                    ),
+            )
+            .arg(
+                Arg::with_name("list-scenes")
+                    .long("list-scenes")
+                    .help("Lists the built-in scenes and exits"),
+            )
+            .get_matches_from(args);
+
+        let mut tone_map: ToneMap =
+            value_t!(matches, "tonemap", ToneMap).unwrap_or_else(|e| e.exit());
+        if let ToneMap::ExtendedReinhard { white } = &mut tone_map {
+            *white = optional_number(&matches, "white");
+        }
+        let transfer = match optional_number(&matches, "gamma") {
+            Some(gamma) => Transfer::Gamma(gamma),
+            None => Transfer::Srgb,
+        };
+
+        Options {
+            scene: matches.value_of("scene").unwrap().to_string(),
+            width: optional_number(&matches, "width"),
+            height: optional_number(&matches, "height"),
+            samples_per_pixel: number(&matches, "spp"),
+            pass_spp: optional_number(&matches, "pass-spp"),
+            adaptive_threshold: optional_number(&matches, "adaptive"),
+            min_spp: number(&matches, "min-spp"),
+            sample_map: matches.value_of("sample-map").map(String::from),
+            max_depth: number(&matches, "max-depth"),
+            threads: optional_number(&matches, "threads").unwrap_or_else(num_cpus::get),
+            tile_size: number(&matches, "tile-size"),
+            outputs: matches
+                .values_of("output")
+                .unwrap()
+                .map(String::from)
+                .collect(),
+            seed: number(&matches, "seed"),
+            display: DisplayTransform {
+                tone_map,
+                exposure: number(&matches, "exposure"),
+                transfer,
+            },
+            export: matches.value_of("export").map(String::from),
+            checkpoint: matches.value_of("checkpoint").map(String::from),
+            resume: matches.value_of("resume").map(String::from),
+            list_scenes: matches.is_present("list-scenes"),
+        }
+    }
+
+    // Image size in pixels; a missing side follows from the other and the
+    // scene's aspect ratio. Pixel centres are spread over (side - 1), so no side
+    // is narrower than two
+    pub fn resolution(&self, aspect_ratio: f32) -> (u32, u32) {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => (width, height),
+            (None, Some(height)) => (((height as f32 * aspect_ratio) as u32).max(2), height),
+            (width, None) => {
+                let width = width.unwrap_or(DEFAULT_WIDTH);
+                (width, ((width as f32 / aspect_ratio) as u32).max(2))
+            }
+        }
+    }
+
+    pub fn is_scene_file(&self) -> bool {
+        self.scene.ends_with(".json")
+            || self.scene.ends_with(".yaml")
+            || self.scene.ends_with(".yml")
+    }
+}
+
+fn number_arg<'a>(name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .value_name("N")
+        .help(help)
+}
+
+fn image_side(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(side) if side >= 2 => Ok(()),
+        _ => Err(String::from("an image side must be at least 2 pixels")),
+    }
+}
+
+fn number<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+    value_t!(matches, name, T).unwrap_or_else(|e| e.exit())
+}
+
+fn optional_number<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(number(matches, name))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution() {
+        // --height alone keeps the scene's shape instead of stretching it
+        let options = Options::parse_from(&["raytracer", "scene.json", "--height", "90"]);
+        assert_eq!(options.width, None);
+        assert_eq!(options.resolution(1.5), (135, 90));
+
+        let options = Options::parse_from(&["raytracer", "--width", "60"]);
+        assert_eq!(options.resolution(1.5), (60, 40));
+        let options = Options::parse_from(&["raytracer", "--width", "60", "--height", "20"]);
+        assert_eq!(options.resolution(1.5), (60, 20));
+        assert_eq!(
+            Options::parse_from(&["raytracer"]).resolution(1.0),
+            (40, 40)
+        );
+
+        // One pixel across would divide by zero when placing pixel centres
+        assert!(image_side(String::from("1")).is_err());
+        assert!(image_side(String::from("2")).is_ok());
+        let options = Options::parse_from(&["raytracer", "--width", "2"]);
+        assert_eq!(options.resolution(4.0), (2, 2));
+    }
+}
@@ -0,0 +1,117 @@
+use super::Framebuffer;
+use crate::base::{color::luminance, Color};
+
+// Relative errors are measured against at least this luminance, so near-black
+// pixels don't chase a vanishing mean
+const MIN_LUMINANCE: f32 = 0.05;
+
+// Running radiance sums and sample counts, resolved into a Framebuffer on demand.
+// The sum of squared sample luminances gives each pixel's variance
+#[derive(Clone)]
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub(super) sum: Vec<Color>,
+    pub(super) sum_sq: Vec<f32>,
+    pub(super) samples: Vec<u32>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        let len = (width * height) as usize;
+        Accumulator {
+            width,
+            height,
+            sum: vec![Color::zero(); len],
+            sum_sq: vec![0.0; len],
+            samples: vec![0; len],
+        }
+    }
+
+    pub fn add(&mut self, x: u32, y: u32, mut sum: Color, sum_sq: f32, count: u32) {
+        // Drop NaN channels before they poison every later pass
+        for i in 0..3 {
+            if sum[i].is_nan() {
+                sum[i] = 0.0;
+            }
+        }
+        let i = (y * self.width + x) as usize;
+        self.sum[i] += sum;
+        if !sum_sq.is_nan() {
+            self.sum_sq[i] += sum_sq;
+        }
+        self.samples[i] += count;
+    }
+
+    pub fn samples(&self, x: u32, y: u32) -> u32 {
+        self.samples[(y * self.width + x) as usize]
+    }
+
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    // Standard error of the pixel's mean luminance relative to that mean;
+    // infinite until there are two samples to estimate a variance from
+    pub fn relative_error(&self, x: u32, y: u32) -> f32 {
+        let i = (y * self.width + x) as usize;
+        let n = self.samples[i] as f32;
+        if n < 2.0 {
+            return f32::INFINITY;
+        }
+        let mean = luminance(&self.sum[i]) / n;
+        let variance = ((self.sum_sq[i] - mean * mean * n) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.abs().max(MIN_LUMINANCE)
+    }
+
+    // Samples per pixel as a grey image
+    pub fn sample_map(&self) -> Framebuffer {
+        let mut fb = Framebuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let n = self.samples(x, y) as f32;
+                fb.set(x, y, Color::new(n, n, n));
+            }
+        }
+        fb
+    }
+
+    pub fn resolve(&self) -> Framebuffer {
+        let mut fb = Framebuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = (y * self.width + x) as usize;
+                if self.samples[i] > 0 {
+                    fb.set(x, y, self.sum[i] / self.samples[i] as f32);
+                }
+            }
+        }
+        fb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_error() {
+        let mut acc = Accumulator::new(2, 1);
+        let flat = Color::new(0.5, 0.5, 0.5);
+        acc.add(0, 0, flat, 0.25, 1);
+        assert!(acc.relative_error(0, 0).is_infinite());
+        for _ in 0..3 {
+            acc.add(0, 0, flat, 0.25, 1);
+        }
+        assert!(acc.relative_error(0, 0) < 1e-3);
+
+        // Samples alternating between 0 and 2 have mean 1 and variance n/(n-1)
+        for i in 0..100 {
+            let v = (i % 2) as f32 * 2.0;
+            acc.add(1, 0, Color::new(v, v, v), v * v, 1);
+        }
+        let expected = (1.0 / 99.0f32).sqrt();
+        assert!((acc.relative_error(1, 0) - expected).abs() < 1e-3);
+        assert_eq!(acc.sample_map().get(1, 0), Color::new(100.0, 100.0, 100.0));
+    }
+}
@@ -0,0 +1,139 @@
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use super::Accumulator;
+use crate::base::Color;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 2;
+
+// Everything that decides what radiance a sample returns
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub scene: String,
+    pub width: u32,
+    pub height: u32,
+    pub max_depth: u16,
+    pub seed: u64,
+}
+
+impl RenderSettings {
+    fn to_value(&self) -> Value {
+        json!({
+            "scene": self.scene,
+            "width": self.width,
+            "height": self.height,
+            "max_depth": self.max_depth,
+            "seed": self.seed,
+        })
+    }
+
+    fn from_value(value: &Value) -> Option<RenderSettings> {
+        Some(RenderSettings {
+            scene: value.get("scene")?.as_str()?.to_string(),
+            width: value.get("width")?.as_u64()? as u32,
+            height: value.get("height")?.as_u64()? as u32,
+            max_depth: value.get("max_depth")?.as_u64()? as u16,
+            seed: value.get("seed")?.as_u64()?,
+        })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Layout: magic, version, settings as length-prefixed JSON, then per pixel the
+// radiance sum as three f32, the luminance sum of squares as f32 and the sample
+// count as u32, all little-endian
+pub fn save(file: &str, settings: &RenderSettings, acc: &Accumulator) -> io::Result<()> {
+    // Write next to the old checkpoint and swap, so a crash mid-write keeps it intact
+    let partial = format!("{}.partial", file);
+    {
+        let mut w = BufWriter::new(File::create(&partial)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        let header = settings.to_value().to_string();
+        w.write_all(&(header.len() as u32).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        for i in 0..acc.sum.len() {
+            let sum = acc.sum[i];
+            for v in &[sum.x, sum.y, sum.z, acc.sum_sq[i]] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&acc.samples[i].to_le_bytes())?;
+        }
+        w.flush()?;
+    }
+    fs::rename(&partial, file)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    read_u32(r).map(f32::from_bits)
+}
+
+pub fn load(file: &str) -> io::Result<(RenderSettings, Accumulator)> {
+    let mut r = BufReader::new(File::open(file)?);
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a render checkpoint"));
+    }
+    if read_u32(&mut r)? != VERSION {
+        return Err(invalid("unsupported checkpoint version"));
+    }
+    let mut header = vec![0; read_u32(&mut r)? as usize];
+    r.read_exact(&mut header)?;
+    let settings = serde_json::from_slice(&header)
+        .ok()
+        .and_then(|value| RenderSettings::from_value(&value))
+        .ok_or_else(|| invalid("malformed checkpoint settings"))?;
+
+    let mut acc = Accumulator::new(settings.width, settings.height);
+    for i in 0..acc.sum.len() {
+        acc.sum[i] = Color::new(read_f32(&mut r)?, read_f32(&mut r)?, read_f32(&mut r)?);
+        acc.sum_sq[i] = read_f32(&mut r)?;
+        acc.samples[i] = read_u32(&mut r)?;
+    }
+    Ok((settings, acc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let settings = RenderSettings {
+            scene: String::from("data/scene_10.json"),
+            width: 3,
+            height: 2,
+            max_depth: 7,
+            seed: u64::MAX,
+        };
+        let mut acc = Accumulator::new(3, 2);
+        acc.add(2, 1, Color::new(1.5, -0.0, 1e20), 3.5, 9);
+        acc.add(0, 0, Color::new(0.25, 0.5, 0.75), 0.25, 1);
+
+        let file = std::env::temp_dir()
+            .join(format!("raytracer_{}_checkpoint.rtck", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        save(&file, &settings, &acc).unwrap();
+        let (loaded_settings, loaded) = load(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded_settings, settings);
+        assert_eq!(loaded.sum, acc.sum);
+        assert_eq!(loaded.sum_sq, acc.sum_sq);
+        assert_eq!(loaded.samples, acc.samples);
+        assert!(load("Cargo.toml").is_err());
+    }
+}
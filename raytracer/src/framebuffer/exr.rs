@@ -0,0 +1,87 @@
+use std::io::{self, Write};
+
+use super::Framebuffer;
+
+const MAGIC: u32 = 20000630;
+const FLOAT: i32 = 2;
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn ints(values: &[i32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect()
+}
+
+fn floats(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect()
+}
+
+// Single-part scanline OpenEXR with uncompressed 32-bit float B, G, R channels
+pub fn write<W: Write>(fb: &Framebuffer, w: &mut W) -> io::Result<()> {
+    let (width, height) = (fb.width as usize, fb.height as usize);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    // Channels are listed, and stored, in alphabetical order
+    let mut channels = Vec::new();
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend(ints(&[FLOAT]));
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend(ints(&[1, 1]));
+    }
+    channels.push(0);
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window = ints(&[0, 0, width as i32 - 1, height as i32 - 1]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &floats(&[1.0]));
+    attribute(
+        &mut header,
+        "screenWindowCenter",
+        "v2f",
+        &floats(&[0.0, 0.0]),
+    );
+    attribute(&mut header, "screenWindowWidth", "float", &floats(&[1.0]));
+    header.push(0);
+    w.write_all(&header)?;
+
+    // One scanline per chunk: y, byte count, then each channel's row
+    let line_size = width * 3 * 4;
+    let first_chunk = header.len() + height * 8;
+    for y in 0..height {
+        let offset = (first_chunk + y * (8 + line_size)) as u64;
+        w.write_all(&offset.to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..fb.height {
+        line.clear();
+        for channel in 0..3 {
+            for x in 0..fb.width {
+                let c = fb.get(x, y);
+                let v = [c.z, c.y, c.x][channel];
+                line.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        w.write_all(&ints(&[y as i32, line_size as i32]))?;
+        w.write_all(&line)?;
+    }
+    Ok(())
+}
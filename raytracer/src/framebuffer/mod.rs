@@ -0,0 +1,179 @@
+pub mod accumulator;
+pub mod checkpoint;
+pub mod exr;
+pub mod pfm;
+
+pub use self::accumulator::Accumulator;
+
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::base::{color::DisplayTransform, Color};
+
+// Linear radiance per pixel, row 0 at the top of the image
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::zero(); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, mut color: Color) {
+        // A NaN sample would otherwise poison the float formats
+        for i in 0..3 {
+            if color[i].is_nan() {
+                color[i] = 0.0;
+            }
+        }
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> RgbImage {
+        let display = display.resolve(&self.pixels);
+        let mut img: RgbImage = ImageBuffer::new(self.width, self.height);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = display.encode(self.get(x, y));
+        }
+        img
+    }
+
+    // The extension picks the format; anything that is not a float format is
+    // tone mapped and goes through the image crate as 8-bit
+    pub fn save(&self, file: &str, display: &DisplayTransform) -> io::Result<()> {
+        let ext = Path::new(file)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("exr") => self.write_with(file, exr::write),
+            Some("pfm") => self.write_with(file, pfm::write),
+            Some("hdr") => self.write_with(file, |fb, w| {
+                let data: Vec<Rgb<f32>> = fb.pixels.iter().map(|c| Rgb([c.x, c.y, c.z])).collect();
+                HdrEncoder::new(w)
+                    .encode(&data, fb.width as usize, fb.height as usize)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }),
+            _ => self
+                .to_rgb8(display)
+                .save(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+
+    fn write_with<F>(&self, file: &str, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&Framebuffer, &mut BufWriter<File>) -> io::Result<()>,
+    {
+        let mut w = BufWriter::new(File::create(file)?);
+        write(self, &mut w)?;
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::hdr::HdrDecoder;
+    use std::fs;
+    use std::io::BufReader;
+
+    fn gradient() -> Framebuffer {
+        let mut fb = Framebuffer::new(9, 4);
+        for y in 0..4 {
+            for x in 0..9 {
+                fb.set(x, y, Color::new(x as f32 * 2.5, y as f32 * 0.125, 40.0));
+            }
+        }
+        fb
+    }
+
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("raytracer_{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&bytes[offset..offset + 4]);
+        f32::from_le_bytes(b)
+    }
+
+    #[test]
+    fn test_hdr_round_trip() {
+        let fb = gradient();
+        let file = temp_file("gradient.hdr");
+        fb.save(&file, &DisplayTransform::default()).unwrap();
+        let decoder = HdrDecoder::new(BufReader::new(File::open(&file).unwrap())).unwrap();
+        let pixels = decoder.read_image_hdr().unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(pixels.len(), 36);
+        for (p, c) in pixels.iter().zip(fb.pixels()) {
+            // RGBE keeps 8 bits of mantissa relative to the largest component
+            assert!((p[0] - c.x).abs() <= 40.0 / 128.0);
+            assert!((p[2] - c.z).abs() <= 40.0 / 128.0);
+        }
+    }
+
+    #[test]
+    fn test_pfm_layout() {
+        let fb = gradient();
+        let file = temp_file("gradient.pfm");
+        fb.save(&file, &DisplayTransform::default()).unwrap();
+        let bytes = fs::read(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        let header = b"PF\n9 4\n-1.0\n";
+        assert!(bytes.starts_with(header));
+        assert_eq!(bytes.len(), header.len() + 9 * 4 * 12);
+        // First stored row is the bottom one
+        let first = header.len() + 12;
+        assert_eq!(f32_at(&bytes, first), 2.5);
+        assert_eq!(f32_at(&bytes, first + 4), 0.375);
+    }
+
+    #[test]
+    fn test_exr_layout() {
+        let fb = gradient();
+        let file = temp_file("gradient.exr");
+        fb.save(&file, &DisplayTransform::default()).unwrap();
+        let bytes = fs::read(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        let line = 9 * 3 * 4;
+        let chunks = bytes.len() - 4 * (8 + line);
+        let mut offset = [0; 8];
+        offset.copy_from_slice(&bytes[chunks - 4 * 8 + 8..chunks - 4 * 8 + 16]);
+        let second = u64::from_le_bytes(offset) as usize;
+        assert_eq!(second, chunks + 8 + line);
+
+        // Row 1 holds B for every x, then G, then R
+        let data = second + 8;
+        assert_eq!(f32_at(&bytes, data), 40.0);
+        assert_eq!(f32_at(&bytes, data + 9 * 4), 0.125);
+        assert_eq!(f32_at(&bytes, data + 2 * 9 * 4 + 4 * 4), 10.0);
+    }
+}
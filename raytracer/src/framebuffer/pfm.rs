@@ -0,0 +1,17 @@
+use std::io::{self, Write};
+
+use super::Framebuffer;
+
+// Portable float map: little-endian RGB floats, rows stored bottom to top
+pub fn write<W: Write>(fb: &Framebuffer, w: &mut W) -> io::Result<()> {
+    write!(w, "PF\n{} {}\n-1.0\n", fb.width, fb.height)?;
+    for y in (0..fb.height).rev() {
+        for x in 0..fb.width {
+            let c = fb.get(x, y);
+            for v in &[c.x, c.y, c.z] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,9 @@
+use crate::base::document::{number, vec3_value};
 use crate::base::{ray::Ray, rtweekend::*, vec3::*};
 use crate::bvh::aabb::AABB;
+use crate::hit::hittable_list::{gather_lights, HittableList};
 use crate::objects::material::Material;
+use serde_json::{json, Value};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -19,13 +22,64 @@ pub trait Hittable: Sync + Send {
 
     fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB>;
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f32 {
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
         0.0
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+
+    // Whether the object's material emits, making the whole object a light
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // Objects that group others add their emissive members to `lights`, placed
+    // where they appear in the world
+    fn collect_lights(&self, lights: &mut HittableList) {}
+
+    fn to_document(&self) -> Option<Value> {
+        None
+    }
+}
+
+impl Hittable for Arc<dyn Hittable> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.as_ref().hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.as_ref().bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.as_ref().pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.as_ref().random(o, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.as_ref().is_emissive()
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.as_ref().collect_lights(lights)
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        self.as_ref().to_document()
+    }
+}
+
+// Density over solid angle of a uniform point on a surface of `area`, hit at `t`
+// along `v` where the surface has `normal`
+pub fn solid_angle_pdf(t: f32, v: &Vec3, normal: &Vec3, area: f32) -> f32 {
+    let squared_distance = t * t * v.squared_length();
+    let cosine = (Vec3::dot(*v, *normal) / v.length()).abs();
+    squared_distance / (cosine * area)
 }
 
 impl HitRecord {
@@ -57,13 +111,10 @@ impl<T: Clone + Hittable> Translate<T> {
 impl<T: Clone + Hittable> Hittable for Translate<T> {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let moved_ray = Ray::new(&(ray.orig - self.offset), &ray.dir, ray.tm);
-        if let Some(rec) = self.ptr.hit(&moved_ray, t_min, t_max) {
-            let mut _rec = rec.clone();
-
-            _rec.p += self.offset;
-            _rec.set_face_normal(&moved_ray, &rec.normal);
-
-            return Some(_rec);
+        if let Some(mut rec) = self.ptr.hit(&moved_ray, t_min, t_max) {
+            // The normal already faces the ray and keeps front_face from the object
+            rec.p += self.offset;
+            return Some(rec);
         }
         None
     }
@@ -78,18 +129,39 @@ impl<T: Clone + Hittable> Hittable for Translate<T> {
         None
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f32 {
-        self.ptr.pdf_value(&(*o - self.offset), v)
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.ptr.pdf_value(&(*o - self.offset), v, time)
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
-        self.ptr.random(&(*o - self.offset))
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.ptr.random(&(*o - self.offset), time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut inner = HittableList::new();
+        self.ptr.collect_lights(&mut inner);
+        for light in inner.objects {
+            lights.add(Arc::new(Translate::new(light, self.offset)));
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Translate",
+            "offset": vec3_value(&self.offset),
+            "object": self.ptr.to_document()?,
+        }))
     }
 }
 
 #[derive(Clone)]
 pub struct RotateY<T: Hittable> {
     pub ptr: T,
+    pub angle: f32,
     pub sin_theta: f32,
     pub cos_theta: f32,
     pub hasbox: bool,
@@ -136,6 +208,7 @@ impl<T: Clone + Hittable> RotateY<T> {
         }
         Self {
             ptr: p.clone(),
+            angle,
             sin_theta,
             cos_theta,
             hasbox,
@@ -167,8 +240,9 @@ impl<T: Clone + Hittable> Hittable for RotateY<T> {
             normal.x = self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z;
             normal.z = -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z;
 
+            // Rotating keeps the normal facing the ray, so front_face still holds
             rec.p = p;
-            rec.set_face_normal(&rotated_ray, &normal);
+            rec.normal = normal;
             return Some(rec);
         }
         None
@@ -181,7 +255,7 @@ impl<T: Clone + Hittable> Hittable for RotateY<T> {
         None
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f32 {
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
         self.ptr.pdf_value(
             &Point3::new(
                 self.cos_theta * o.x - self.sin_theta * o.z,
@@ -193,22 +267,43 @@ impl<T: Clone + Hittable> Hittable for RotateY<T> {
                 v.y,
                 self.sin_theta * v.x + self.cos_theta * v.z,
             ),
+            time,
         )
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
         let p = Point3::new(
             self.cos_theta * o.x - self.sin_theta * o.z,
             o.y,
             self.sin_theta * o.x + self.cos_theta * o.z,
         );
-        let v = self.random(&p);
+        let v = self.ptr.random(&p, time);
         Vec3::new(
             self.cos_theta * v.x + self.sin_theta * v.z,
             v.y,
             -self.sin_theta * v.x + self.cos_theta * v.z,
         )
     }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut inner = HittableList::new();
+        self.ptr.collect_lights(&mut inner);
+        for light in inner.objects {
+            lights.add(Arc::new(RotateY::new(light, self.angle)));
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "RotateY",
+            "angle": number(self.angle),
+            "object": self.ptr.to_document()?,
+        }))
+    }
 }
 
 pub struct FlipFace {
@@ -233,4 +328,111 @@ impl Hittable for FlipFace {
     fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
         self.ptr.bounding_box(time0, time1)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.ptr.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.ptr.random(o, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut inner = HittableList::new();
+        self.ptr.collect_lights(&mut inner);
+        for light in inner.objects {
+            lights.add(Arc::new(FlipFace::new(light)));
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "FlipFace",
+            "object": self.ptr.to_document()?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::arrect::{Box, XYRect, XZRect, YZRect};
+    use crate::objects::constant_medium::ConstantMedium;
+    use crate::objects::material::{DiffuseLight, Isotropic};
+    use crate::objects::moving_sphere::MovingSphere;
+    use crate::objects::sphere::Sphere;
+    use crate::objects::texture::SolidColor;
+
+    #[test]
+    fn test_light_sampling() {
+        let light = DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0));
+        let cube: Arc<dyn Hittable> = Arc::new(Box::new(
+            Point3::new(-1.0, -1.0, 1.5),
+            Point3::new(1.0, 1.0, 3.0),
+            light.clone(),
+        ));
+        let shapes: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(XYRect::new(-1.0, 1.0, -1.0, 2.0, 1.0, light.clone())),
+            Arc::new(YZRect::new(-1.0, 1.0, -1.0, 1.0, -1.0, light.clone())),
+            Arc::new(XZRect::new(-1.0, 1.0, -1.0, 1.0, 1.0, light.clone())),
+            cube.clone(),
+            Arc::new(MovingSphere::new(
+                &Point3::new(0.0, 0.0, -3.0),
+                &Point3::new(0.0, 0.0, 3.0),
+                0.0,
+                1.0,
+                1.0,
+                light.clone(),
+            )),
+            Arc::new(ConstantMedium::new(
+                Sphere::new(&Point3::new(2.0, 0.0, 0.0), 1.2, light.clone()),
+                0.5,
+                Isotropic::new(SolidColor::new(1.0, 1.0, 1.0)),
+            )),
+            // The origin is inside this one, as shading points in a medium are
+            Arc::new(ConstantMedium::new(
+                Sphere::new(&Point3::new(0.3, -0.2, 0.1), 2.0, light.clone()),
+                0.5,
+                Isotropic::new(SolidColor::new(1.0, 1.0, 1.0)),
+            )),
+            Arc::new(RotateY::new(cube.clone(), 30.0)),
+            Arc::new(Translate::new(cube.clone(), Vec3::new(0.0, 1.5, 0.0))),
+            Arc::new(FlipFace::new(cube)),
+        ];
+
+        seed_rng(5);
+        let o = Point3::zero();
+        // The moving sphere is centred 1.5 below the origin along z at this time
+        let time = 0.25;
+        for shape in &shapes {
+            let n = 50000;
+            let mut estimate = 0.0;
+            for _ in 0..n {
+                let v = random_in_unit_vector();
+                estimate += shape.pdf_value(&o, &v, time) * 4.0 * PI / n as f32;
+            }
+            assert!((estimate - 1.0).abs() < 0.05, "{}", estimate);
+            for _ in 0..100 {
+                let v = shape.random(&o, time);
+                assert!(shape.pdf_value(&o, &v, time) > 0.0);
+            }
+        }
+
+        // Seen from inside, moved and rotated spheres still report their back face
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(&Point3::zero(), 1.0, light));
+        let moved: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Translate::new(sphere.clone(), Vec3::new(0.0, 0.0, 5.0))),
+            Arc::new(RotateY::new(sphere, 45.0)),
+        ];
+        let centres = [Point3::new(0.0, 0.0, 5.0), Point3::zero()];
+        for (object, centre) in moved.iter().zip(centres.iter()) {
+            let ray = Ray::new(centre, &Vec3::new(1.0, 0.0, 0.0), 0.0);
+            let rec = object.hit(&ray, 0.001, INF).unwrap();
+            assert!(!rec.front_face && Vec3::dot(rec.normal, ray.dir) < 0.0);
+        }
+    }
 }
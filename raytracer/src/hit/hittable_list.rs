@@ -1,8 +1,9 @@
-use crate::base::rtweekend::random_u_m;
+use crate::base::rtweekend::random_index;
 use crate::base::{ray::*, vec3::Point3};
 use crate::base::{Color, Vec3};
 use crate::bvh::aabb::{surrounding_box, AABB};
 use crate::hit::hittable::*;
+use serde_json::{json, Value};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -24,6 +25,22 @@ impl HittableList {
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
         (*self).objects.push(object);
     }
+
+    // Every emissive object in the list, for sampling the lights directly
+    pub fn lights(&self) -> HittableList {
+        let mut lights = HittableList::new();
+        self.collect_lights(&mut lights);
+        lights
+    }
+}
+
+// Adds `object` itself if it emits, otherwise whatever lights it groups
+pub fn gather_lights(object: &Arc<dyn Hittable>, lights: &mut HittableList) {
+    if object.is_emissive() {
+        lights.add(object.clone());
+    } else {
+        object.collect_lights(lights);
+    }
 }
 
 impl Hittable for HittableList {
@@ -45,7 +62,7 @@ impl Hittable for HittableList {
             return None;
         }
 
-        let first_box = true;
+        let mut first_box = true;
         let mut output_box = AABB {
             //useless initialize but to assure syntax legal
             maximum: Point3::ones(),
@@ -55,6 +72,7 @@ impl Hittable for HittableList {
             if let Some(_aabb) = (*object).bounding_box(time0, time1) {
                 if first_box {
                     output_box = _aabb;
+                    first_box = false;
                 } else {
                     output_box = surrounding_box(&output_box, &_aabb)
                 }
@@ -65,17 +83,40 @@ impl Hittable for HittableList {
         Some(output_box)
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f32 {
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
         let weight = 1.0 / self.objects.len() as f32;
         let mut sum = 0.0;
         for item in self.objects.iter() {
-            sum += item.pdf_value(o, v) * weight;
+            sum += item.pdf_value(o, v, time) * weight;
         }
         sum
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
-        let index = random_u_m(0, self.objects.len() as u16);
-        self.objects[index as usize].random(o)
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let index = random_index(self.objects.len());
+        self.objects[index].random(o, time)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for object in &self.objects {
+            gather_lights(object, lights);
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut items = Vec::new();
+        for object in &self.objects {
+            items.push(object.to_document()?);
+        }
+        Some(json!({
+            "type": "HitableList",
+            "items": items,
+        }))
     }
 }
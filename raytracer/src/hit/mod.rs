@@ -1,2 +1,3 @@
 pub mod hittable;
 pub mod hittable_list;
+pub mod transform;
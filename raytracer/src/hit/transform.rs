@@ -0,0 +1,227 @@
+use crate::base::document::matrix_value;
+use crate::base::{matrix::Mat4, ray::Ray, rtweekend::*, vec3::*};
+use crate::bvh::{aabb::AABB, bvh::Bvh};
+use crate::hit::hittable::{HitRecord, Hittable};
+use crate::hit::hittable_list::HittableList;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+// Places an object with an arbitrary affine matrix. The object is shared, not
+// copied, so any number of transforms can point at one mesh or BVH
+#[derive(Clone)]
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    normal_matrix: Mat4,
+}
+
+impl Transform {
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Transform {
+        let inverse = matrix.inverse().expect("singular transform");
+        Transform {
+            object,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // The direction stays unnormalized so t means the same in both spaces
+        let local = Ray::new(
+            &self.inverse.transform_point(&ray.orig),
+            &self.inverse.transform_vector(&ray.dir),
+            ray.tm,
+        );
+        let mut rec = self.object.hit(&local, t_min, t_max)?;
+        rec.p = self.matrix.transform_point(&rec.p);
+        // Affine maps keep the sign of dot(dir, normal), so front_face still holds
+        rec.normal = self.normal_matrix.transform_vector(&rec.normal).unit();
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        let bbox = self.object.bounding_box(time0, time1)?;
+        let mut min = Point3::new(INF, INF, INF);
+        let mut max = Point3::new(-INF, -INF, -INF);
+        for corner in 0..8 {
+            let p = Point3::new(
+                if corner & 1 == 0 {
+                    bbox.minimum.x
+                } else {
+                    bbox.maximum.x
+                },
+                if corner & 2 == 0 {
+                    bbox.minimum.y
+                } else {
+                    bbox.maximum.y
+                },
+                if corner & 4 == 0 {
+                    bbox.minimum.z
+                } else {
+                    bbox.maximum.z
+                },
+            );
+            let p = self.matrix.transform_point(&p);
+            for i in 0..3 {
+                min[i] = fmin(min[i], p[i]);
+                max[i] = fmax(max[i], p[i]);
+            }
+        }
+        Some(AABB::new(&min, &max))
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        let local = self.inverse.transform_vector(&v.unit());
+        let pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(o), &local, time);
+        // Solid angle changes by |det A| / |A v|^3 when directions go through A
+        let length = local.length();
+        pdf * self.inverse.determinant3().abs() / (length * length * length)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        let local = self.object.random(&self.inverse.transform_point(o), time);
+        self.matrix.transform_vector(&local)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut inner = HittableList::new();
+        self.object.collect_lights(&mut inner);
+        for light in inner.objects {
+            lights.add(Arc::new(Transform::new(light, self.matrix)));
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Transform",
+            "transform": [{ "matrix": matrix_value(&self.matrix) }],
+            "object": self.object.to_document()?,
+        }))
+    }
+}
+
+// One object placed many times, with a BVH over the placements
+#[derive(Clone)]
+pub struct Instances {
+    object: Arc<dyn Hittable>,
+    matrices: Vec<Mat4>,
+    tree: Bvh,
+}
+
+impl Instances {
+    pub fn new(object: Arc<dyn Hittable>, matrices: Vec<Mat4>) -> Instances {
+        let placed = matrices
+            .iter()
+            .map(|m| Arc::new(Transform::new(object.clone(), *m)) as Arc<dyn Hittable>)
+            .collect();
+        Instances {
+            object,
+            matrices,
+            tree: Bvh::new(placed, 0.0, 1.0),
+        }
+    }
+}
+
+impl Hittable for Instances {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.tree.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.tree.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.tree.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.tree.random(o, time)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.tree.collect_lights(lights)
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let transforms: Vec<Value> = self
+            .matrices
+            .iter()
+            .map(|m| json!([{ "matrix": matrix_value(m) }]))
+            .collect();
+        Some(json!({
+            "type": "Instances",
+            "object": self.object.to_document()?,
+            "transforms": transforms,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::material::Lambertian;
+    use crate::objects::sphere::Sphere;
+    use crate::objects::texture::SolidColor;
+
+    #[test]
+    fn test_scaled_sphere() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            &Point3::zero(),
+            1.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        ));
+        let matrix = Mat4::translate(Vec3::new(0.0, 0.0, -5.0))
+            * Mat4::rotate(Vec3::new(0.0, 1.0, 0.0), 30.0)
+            * Mat4::scale(Vec3::new(2.0, 1.0, 1.0));
+        let ellipsoid = Transform::new(sphere.clone(), matrix);
+
+        let rec = ellipsoid
+            .hit(
+                &Ray::new(&Point3::zero(), &Vec3::new(0.0, 1.0, -10.0), 0.0),
+                0.001,
+                INF,
+            )
+            .unwrap();
+        let local = matrix.inverse().unwrap().transform_point(&rec.p);
+        assert!((local.length() - 1.0).abs() < 1e-4);
+        assert!(rec.front_face && Vec3::dot(rec.normal, Vec3::new(0.0, 1.0, -10.0)) < 0.0);
+        assert!((rec.normal.length() - 1.0).abs() < 1e-4);
+
+        let bbox = ellipsoid.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.minimum.y < -0.99 && bbox.maximum.y > 0.99);
+        assert!(bbox.maximum.x - bbox.minimum.x > 3.4);
+
+        // The sampled directions must integrate to one under pdf_value
+        seed_rng(3);
+        let o = Point3::new(0.5, 0.2, 0.0);
+        let n = 20000;
+        let mut estimate = 0.0;
+        for _ in 0..n {
+            let v = random_in_unit_vector();
+            estimate += ellipsoid.pdf_value(&o, &v, 0.0) * 4.0 * PI / n as f32;
+        }
+        assert!((estimate - 1.0).abs() < 0.05, "{}", estimate);
+
+        let instances = Instances::new(
+            sphere,
+            vec![matrix, Mat4::translate(Vec3::new(0.0, 0.0, 5.0))],
+        );
+        let behind = Ray::new(&Point3::zero(), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!((instances.hit(&behind, 0.001, INF).unwrap().t - 4.0).abs() < 1e-4);
+    }
+}
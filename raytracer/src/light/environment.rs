@@ -0,0 +1,283 @@
+use image::codecs::hdr::HdrDecoder;
+use image::{open, ImageResult};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::Environment;
+use crate::base::document::{number, vec3_value};
+use crate::base::{color::luminance, color::srgb_eotf, rtweekend::*, Color, Vec3};
+
+// The same radiance from every direction; this is the plain background colour
+pub struct UniformEnvironment {
+    pub color: Color,
+}
+
+impl UniformEnvironment {
+    pub fn new(color: Color) -> UniformEnvironment {
+        UniformEnvironment { color }
+    }
+}
+
+impl Environment for UniformEnvironment {
+    fn value(&self, direction: &Vec3) -> Color {
+        self.color
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(vec3_value(&self.color))
+    }
+}
+
+// Piecewise-constant distribution over n bins, proportional to their weights
+struct Distribution {
+    weights: Vec<f32>,
+    cdf: Vec<f32>,
+    total: f32,
+}
+
+impl Distribution {
+    fn new(weights: Vec<f32>) -> Distribution {
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut sum = 0.0f64;
+        cdf.push(0.0);
+        for w in &weights {
+            sum += *w as f64;
+            cdf.push(sum as f32);
+        }
+        if sum > 0.0 {
+            for c in cdf.iter_mut() {
+                *c /= sum as f32;
+            }
+            *cdf.last_mut().unwrap() = 1.0;
+        }
+        Distribution {
+            weights,
+            cdf,
+            total: sum as f32,
+        }
+    }
+
+    fn pmf(&self, i: usize) -> f32 {
+        if self.total > 0.0 {
+            self.weights[i] / self.total
+        } else {
+            0.0
+        }
+    }
+
+    // The bin whose slice of the cdf contains u, which never has zero weight
+    fn sample(&self, u: f32) -> usize {
+        let i = self.cdf[1..].partition_point(|&c| c <= u);
+        i.min(self.weights.len() - 1)
+    }
+}
+
+// Equirectangular (lat-long) image around the scene. +y is the top row, and
+// with no rotation the centre of the image lies along -z; `rotation` turns the
+// map about +y in degrees. Directions are importance sampled by pixel
+// luminance, weighted by the solid angle each row covers
+pub struct EnvironmentMap {
+    pub file_path: String,
+    pub width: usize,
+    pub height: usize,
+    pub rotation: f32,
+    pub intensity: f32,
+    pixels: Vec<Color>,
+    rows: Distribution,
+    columns: Vec<Distribution>,
+}
+
+impl EnvironmentMap {
+    // Radiance .hdr files are read as they are, other images are decoded from sRGB
+    pub fn load(file_path: &str, rotation: f32, intensity: f32) -> ImageResult<EnvironmentMap> {
+        let is_hdr = Path::new(file_path)
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("hdr"));
+        let (width, height, pixels) = if is_hdr {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(file_path)?))?;
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .iter()
+                .map(|p| Color::new(p[0], p[1], p[2]))
+                .collect();
+            (meta.width, meta.height, pixels)
+        } else {
+            let image = open(file_path)?.to_rgb8();
+            let decode = |v: u8| srgb_eotf(v as f32 / 255.0);
+            let pixels = image
+                .pixels()
+                .map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2])))
+                .collect();
+            (image.width(), image.height(), pixels)
+        };
+        Ok(EnvironmentMap::from_pixels(
+            file_path,
+            width as usize,
+            height as usize,
+            pixels,
+            rotation,
+            intensity,
+        ))
+    }
+
+    // `pixels` holds linear radiance row by row, starting from the top
+    pub fn from_pixels(
+        file_path: &str,
+        width: usize,
+        height: usize,
+        pixels: Vec<Color>,
+        rotation: f32,
+        intensity: f32,
+    ) -> EnvironmentMap {
+        let mut columns = Vec::with_capacity(height);
+        let mut row_weights = Vec::with_capacity(height);
+        for j in 0..height {
+            let sin_theta = (PI * (j as f32 + 0.5) / height as f32).sin();
+            let weights: Vec<f32> = pixels[j * width..(j + 1) * width]
+                .iter()
+                .map(|c| luminance(c).max(0.0) * sin_theta)
+                .collect();
+            row_weights.push(weights.iter().sum());
+            columns.push(Distribution::new(weights));
+        }
+        EnvironmentMap {
+            file_path: file_path.to_string(),
+            width,
+            height,
+            rotation,
+            intensity,
+            pixels,
+            rows: Distribution::new(row_weights),
+            columns,
+        }
+    }
+
+    // Pixel column and row a direction falls in, and the sine of its polar angle
+    fn pixel(&self, direction: &Vec3) -> (usize, usize, f32) {
+        let d = direction.unit();
+        let theta = clamp(d.y, -1.0, 1.0).acos();
+        let phi = d.x.atan2(-d.z) - degrees_to_radians(self.rotation);
+        let u = phi / (2.0 * PI) + 0.5;
+        let u = u - u.floor();
+        let i = ((u * self.width as f32) as usize).min(self.width - 1);
+        let j = ((theta / PI * self.height as f32) as usize).min(self.height - 1);
+        // Taken from x and z, since y rounds to 1 well before the pole
+        (i, j, (d.x * d.x + d.z * d.z).sqrt())
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, direction: &Vec3) -> Color {
+        let (i, j, _) = self.pixel(direction);
+        self.pixels[j * self.width + i] * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        self.rows.total > 0.0
+    }
+
+    // The image maps to the sphere with dA = 2 pi^2 sin(theta) du dv
+    fn pdf_value(&self, direction: &Vec3) -> f32 {
+        let (i, j, sin_theta) = self.pixel(direction);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let p = self.rows.pmf(j) * self.columns[j].pmf(i);
+        p * (self.width * self.height) as f32 / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random(&self) -> Vec3 {
+        let j = self.rows.sample(random_f());
+        let i = self.columns[j].sample(random_f());
+        let u = (i as f32 + random_f()) / self.width as f32;
+        let v = (j as f32 + random_f()) / self.height as f32;
+        let theta = v * PI;
+        let phi = 2.0 * PI * (u - 0.5) + degrees_to_radians(self.rotation);
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "EnvironmentMap",
+            "file": self.file_path,
+            "rotation": number(self.rotation),
+            "intensity": number(self.intensity),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::vec3::random_in_unit_vector;
+
+    fn spot_map(rotation: f32) -> EnvironmentMap {
+        let (width, height) = (8, 4);
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+        pixels[width + 5] = Color::new(20.0, 10.0, 5.0);
+        pixels[2 * width + 1] = Color::zero();
+        EnvironmentMap::from_pixels("", width, height, pixels, rotation, 2.0)
+    }
+
+    #[test]
+    fn test_environment_map_sampling() {
+        seed_rng(11);
+        let map = spot_map(30.0);
+        assert!(map.is_sampled());
+
+        // Uniform directions estimate the integral of the pdf over the sphere
+        let n = 200000;
+        let mut integral = 0.0;
+        for _ in 0..n {
+            let v = random_in_unit_vector();
+            integral += map.pdf_value(&v) * 4.0 * PI / n as f32;
+        }
+        assert!((integral - 1.0).abs() < 0.02);
+
+        // Samples land in each pixel as often as the pdf says, and never in a black one
+        let mut bright = 0;
+        for _ in 0..n {
+            let v = map.random();
+            assert!(map.pdf_value(&v) > 0.0);
+            let (i, j, _) = map.pixel(&v);
+            assert!(!(i == 1 && j == 2));
+            if i == 5 && j == 1 {
+                bright += 1;
+            }
+        }
+        let expected = map.rows.pmf(1) * map.columns[1].pmf(5);
+        assert!((bright as f32 / n as f32 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_environment_map_rotation() {
+        let map = spot_map(0.0);
+        let bright = Color::new(40.0, 20.0, 10.0);
+        // Column 5 of 8 spans 45 to 90 degrees from -z towards +x
+        let phi = degrees_to_radians(67.5);
+        let theta = degrees_to_radians(67.5);
+        let v = Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+        assert_eq!(map.value(&v), bright);
+
+        let turned = spot_map(90.0);
+        assert_ne!(turned.value(&v), bright);
+        let phi = phi + degrees_to_radians(90.0);
+        let v = Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+        assert_eq!(turned.value(&v), bright);
+    }
+}
@@ -0,0 +1,102 @@
+pub mod environment;
+pub mod punctual;
+pub mod sky;
+
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::base::{rtweekend::random_f, Color, Point3, Vec3};
+use crate::hit::{hittable::Hittable, hittable_list::HittableList};
+
+// Radiance arriving from infinitely far away, seen by every ray that leaves the scene
+pub trait Environment: Sync + Send {
+    fn value(&self, direction: &Vec3) -> Color;
+
+    // Environments that can't be sampled are only found by BSDF samples
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f32 {
+        0.0
+    }
+
+    fn random(&self) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        None
+    }
+}
+
+// A direction towards a light, how far along it the light is, and the radiance
+// arriving divided by the density of having picked that direction
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    pub radiance: Color,
+}
+
+// Lights without a surface to hit, which only shadow rays can reach
+pub trait PunctualLight: Sync + Send {
+    fn sample(&self, p: &Point3) -> Option<LightSample>;
+
+    fn to_document(&self) -> Option<Value> {
+        None
+    }
+}
+
+// Everything next-event estimation aims at: the emissive objects of the scene,
+// the environment when it can be sampled, and the punctual lights
+pub struct Lights {
+    pub objects: HittableList,
+    pub environment: Arc<dyn Environment>,
+    pub punctual: Vec<Arc<dyn PunctualLight>>,
+}
+
+impl Lights {
+    pub fn new(
+        objects: HittableList,
+        environment: Arc<dyn Environment>,
+        punctual: Vec<Arc<dyn PunctualLight>>,
+    ) -> Lights {
+        Lights {
+            objects,
+            environment,
+            punctual,
+        }
+    }
+
+    // Probability of aiming at the environment rather than the objects
+    fn environment_weight(&self) -> f32 {
+        if !self.environment.is_sampled() {
+            0.0
+        } else if self.objects.objects.is_empty() {
+            1.0
+        } else {
+            0.5
+        }
+    }
+
+    pub fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        let weight = self.environment_weight();
+        let mut pdf = 0.0;
+        if weight < 1.0 {
+            pdf += (1.0 - weight) * self.objects.pdf_value(o, v, time);
+        }
+        if weight > 0.0 {
+            pdf += weight * self.environment.pdf_value(v);
+        }
+        pdf
+    }
+
+    pub fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        let weight = self.environment_weight();
+        if weight == 1.0 || (weight > 0.0 && random_f() < weight) {
+            self.environment.random()
+        } else {
+            self.objects.random(o, time)
+        }
+    }
+}
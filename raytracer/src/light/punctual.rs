@@ -0,0 +1,223 @@
+use serde_json::{json, Value};
+
+use super::{LightSample, PunctualLight};
+use crate::base::document::{number, vec3_value};
+use crate::base::onb::Onb;
+use crate::base::{rtweekend::*, Color, Point3, Vec3};
+use crate::objects::sphere::random_to_sphere;
+
+// Light spreading from a point with inverse-square falloff; `intensity` is in
+// W/sr. A `range` fades it smoothly to nothing at that distance
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Color,
+    pub range: Option<f32>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color, range: Option<f32>) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            range,
+        }
+    }
+
+    fn sample_towards(&self, p: &Point3) -> Option<(Vec3, f32, Color)> {
+        let offset = self.position - *p;
+        let distance_squared = offset.squared_length();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let window = match self.range {
+            Some(range) => {
+                let ratio = (distance / range).powi(4);
+                (1.0 - ratio).max(0.0).powi(2)
+            }
+            None => 1.0,
+        };
+        if window <= 0.0 {
+            return None;
+        }
+        let radiance = self.intensity * (window / distance_squared);
+        Some((offset / distance, distance, radiance))
+    }
+}
+
+impl PunctualLight for PointLight {
+    fn sample(&self, p: &Point3) -> Option<LightSample> {
+        let (direction, distance, radiance) = self.sample_towards(p)?;
+        Some(LightSample {
+            direction,
+            distance,
+            radiance,
+        })
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut doc = json!({
+            "type": "PointLight",
+            "position": vec3_value(&self.position),
+            "intensity": vec3_value(&self.intensity),
+        });
+        if let Some(range) = self.range {
+            doc["range"] = number(range);
+        }
+        Some(doc)
+    }
+}
+
+// A point light shining into a cone around `direction`. Full intensity within
+// `inner_angle` of the axis, fading out by `outer_angle`, both half-angles in degrees
+pub struct SpotLight {
+    pub light: PointLight,
+    pub direction: Vec3,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        light: PointLight,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> SpotLight {
+        let outer_angle = outer_angle.max(inner_angle);
+        SpotLight {
+            light,
+            direction,
+            inner_angle,
+            outer_angle,
+            cos_inner: degrees_to_radians(inner_angle).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl PunctualLight for SpotLight {
+    fn sample(&self, p: &Point3) -> Option<LightSample> {
+        let (direction, distance, radiance) = self.light.sample_towards(p)?;
+        let falloff = self.falloff(-Vec3::dot(direction, self.direction.unit()));
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: radiance * falloff,
+        })
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut doc = json!({
+            "type": "SpotLight",
+            "position": vec3_value(&self.light.position),
+            "direction": vec3_value(&self.direction),
+            "intensity": vec3_value(&self.light.intensity),
+            "inner_angle": number(self.inner_angle),
+            "outer_angle": number(self.outer_angle),
+        });
+        if let Some(range) = self.light.range {
+            doc["range"] = number(range);
+        }
+        Some(doc)
+    }
+}
+
+// Light from infinitely far away, arriving from `direction` with `irradiance`
+// in W/m^2 on a surface facing it. A non-zero `angular_radius` (degrees) spreads
+// it over a disk for soft shadows
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Color,
+    pub angular_radius: f32,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color, angular_radius: f32) -> DirectionalLight {
+        DirectionalLight {
+            direction,
+            irradiance,
+            angular_radius,
+        }
+    }
+}
+
+impl PunctualLight for DirectionalLight {
+    // Uniform over the disk, so radiance over density is the irradiance itself
+    fn sample(&self, p: &Point3) -> Option<LightSample> {
+        let direction = if self.angular_radius > 0.0 {
+            let uvw = Onb::build_from_w(&self.direction);
+            let sin_max = degrees_to_radians(self.angular_radius.min(90.0)).sin();
+            uvw.local(&random_to_sphere(sin_max, 1.0))
+        } else {
+            self.direction.unit()
+        };
+        Some(LightSample {
+            direction,
+            distance: INF,
+            radiance: self.irradiance,
+        })
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "DirectionalLight",
+            "direction": vec3_value(&self.direction),
+            "irradiance": vec3_value(&self.irradiance),
+            "angular_radius": number(self.angular_radius),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punctual_lights() {
+        let white = Color::new(4.0, 4.0, 4.0);
+        let point = PointLight::new(Point3::new(0.0, 2.0, 0.0), white, None);
+        let sample = point.sample(&Point3::zero()).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, 2.0);
+        assert_eq!(sample.radiance, Color::new(1.0, 1.0, 1.0));
+
+        let ranged = PointLight::new(Point3::new(0.0, 2.0, 0.0), white, Some(3.0));
+        assert!(ranged.sample(&Point3::zero()).unwrap().radiance.x < 1.0);
+        assert!(ranged.sample(&Point3::new(0.0, -1.5, 0.0)).is_none());
+
+        // Straight down the axis, in the soft edge, and outside the cone
+        let spot = SpotLight::new(point, Vec3::new(0.0, -1.0, 0.0), 20.0, 40.0);
+        assert_eq!(spot.sample(&Point3::zero()).unwrap().radiance.x, 1.0);
+        let edge = Point3::new(2.0 * degrees_to_radians(30.0).tan(), 0.0, 0.0);
+        let falloff = spot.sample(&edge).unwrap().radiance.x * (4.0 + edge.x * edge.x) / 4.0;
+        assert!(falloff > 0.0 && falloff < 1.0);
+        assert!(spot.sample(&Point3::new(4.0, 0.0, 0.0)).is_none());
+
+        seed_rng(2);
+        let sun = DirectionalLight::new(Vec3::new(1.0, 1.0, 0.0), white, 2.0);
+        let axis = Vec3::new(1.0, 1.0, 0.0).unit();
+        for _ in 0..1000 {
+            let sample = sun.sample(&Point3::zero()).unwrap();
+            assert!((sample.direction.length() - 1.0).abs() < 1e-4);
+            assert!(Vec3::dot(sample.direction, axis) >= degrees_to_radians(2.0).cos() - 1e-6);
+            assert_eq!(sample.radiance, white);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use serde_json::{json, Value};
+
+use super::environment::EnvironmentMap;
+use super::Environment;
+use crate::base::document::{number, vec3_value};
+use crate::base::onb::Onb;
+use crate::base::{color::luminance, rtweekend::*, Color, Vec3};
+use crate::objects::sphere::random_to_sphere;
+
+// Render units per kcd/m^2, which puts a clear zenith near the 0.7 to 1.0 of the
+// hand-picked sky colour
+const KCD: f32 = 0.1;
+// Luminance of the sun outside the atmosphere, in kcd/m^2
+const SUN_LUMINANCE: f32 = 2.0e6;
+// Resolution of the table the sky is importance sampled from; an even height
+// puts the horizon on a row boundary
+const TABLE_WIDTH: usize = 64;
+const TABLE_HEIGHT: usize = 32;
+
+// Preetham, Shirley and Smits' analytic daylight model with a sun disk. The
+// ground below the horizon is a Lambertian plane of `ground_albedo` lit by the
+// sky and sun. `sun_radius` is the angular radius of the disk in degrees
+pub struct PhysicalSky {
+    pub sun_direction: Vec3,
+    pub turbidity: f32,
+    pub ground_albedo: Color,
+    pub sun_radius: f32,
+    pub intensity: f32,
+    sun: Vec3,
+    // Perez coefficients A to E for Y, x and y, and their zenith values
+    perez: [[f32; 5]; 3],
+    zenith: [f32; 3],
+    sun_theta: f32,
+    sun_radiance: Color,
+    cos_sun_max: f32,
+    ground: Color,
+    table: EnvironmentMap,
+    sun_weight: f32,
+}
+
+impl PhysicalSky {
+    pub fn new(
+        sun_direction: Vec3,
+        turbidity: f32,
+        ground_albedo: Color,
+        sun_radius: f32,
+        intensity: f32,
+    ) -> PhysicalSky {
+        let sun = sun_direction.unit();
+        let t = clamp(turbidity, 1.7, 10.0);
+        // The model only covers a sun above the horizon
+        let sun_theta = clamp(sun.y, 0.0, 1.0).acos();
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_theta);
+        let (s, s2, s3) = (sun_theta, sun_theta * sun_theta, sun_theta.powi(3));
+        let zenith = [
+            ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * KCD * intensity,
+            t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s)
+                + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394)
+                + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886),
+            t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s)
+                + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516)
+                + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688),
+        ];
+
+        // Rayleigh and aerosol extinction along the path to the sun, at the
+        // wavelengths (in micrometres) standing in for red, green and blue
+        let sun_visible = sun.y > 0.0 && sun_radius > 0.0;
+        let air_mass =
+            1.0 / (sun_theta.cos() + 0.15 * (93.885 - sun_theta.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let transmittance = |lambda: f32| {
+            let tau = 0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3);
+            (-air_mass * tau).exp()
+        };
+        let sun_radiance = match sun_visible {
+            true => {
+                Color::new(
+                    transmittance(0.68),
+                    transmittance(0.55),
+                    transmittance(0.44),
+                ) * (SUN_LUMINANCE * KCD * intensity)
+            }
+            false => Color::zero(),
+        };
+        let sin_sun_max = degrees_to_radians(sun_radius.min(90.0)).sin();
+
+        let mut sky = PhysicalSky {
+            sun_direction,
+            turbidity,
+            sun,
+            ground_albedo,
+            sun_radius,
+            intensity,
+            perez,
+            zenith,
+            sun_theta,
+            sun_radiance,
+            cos_sun_max: (1.0 - sin_sun_max * sin_sun_max).sqrt(),
+            ground: Color::zero(),
+            table: EnvironmentMap::from_pixels("", 1, 1, vec![Color::zero()], 0.0, 1.0),
+            sun_weight: 0.0,
+        };
+
+        // Tabulate the sky at pixel centres, adding up its power for choosing
+        // between the table and the sun and its irradiance on the ground
+        let (width, height) = (TABLE_WIDTH, TABLE_HEIGHT);
+        let mut pixels = vec![Color::zero(); width * height];
+        let mut irradiance = sky.sun_radiance * (sky.solid_angle() * sun.y.max(0.0));
+        let mut sky_power = 0.0;
+        for j in 0..height / 2 {
+            let theta = PI * (j as f32 + 0.5) / height as f32;
+            let d_omega = 2.0 * PI * PI * theta.sin() / (width * height) as f32;
+            for i in 0..width {
+                let phi = 2.0 * PI * ((i as f32 + 0.5) / width as f32 - 0.5);
+                let direction = Vec3::new(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                );
+                let radiance = sky.sky_radiance(&direction);
+                pixels[j * width + i] = radiance;
+                irradiance += radiance * (theta.cos() * d_omega);
+                sky_power += luminance(&radiance) * d_omega;
+            }
+        }
+        sky.ground = Vec3::elemul(ground_albedo, irradiance) / PI;
+        for pixel in pixels[width * height / 2..].iter_mut() {
+            *pixel = sky.ground;
+        }
+        sky_power += luminance(&sky.ground) * 2.0 * PI;
+        let sun_power = luminance(&sky.sun_radiance) * sky.solid_angle();
+        if sun_power + sky_power > 0.0 {
+            sky.sun_weight = sun_power / (sun_power + sky_power);
+        }
+        sky.table = EnvironmentMap::from_pixels("", width, height, pixels, 0.0, 1.0);
+        sky
+    }
+
+    fn solid_angle(&self) -> f32 {
+        2.0 * PI * (1.0 - self.cos_sun_max)
+    }
+
+    fn perez(&self, channel: usize, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.perez[channel];
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+
+    // Sky radiance above the horizon, without the sun disk
+    fn sky_radiance(&self, direction: &Vec3) -> Color {
+        let cos_theta = direction.y.max(1e-4);
+        let gamma = clamp(Vec3::dot(*direction, self.sun), -1.0, 1.0).acos();
+        let mut xyy = [0.0; 3];
+        for (channel, value) in xyy.iter_mut().enumerate() {
+            *value = self.zenith[channel] * self.perez(channel, cos_theta, gamma)
+                / self.perez(channel, 1.0, self.sun_theta);
+        }
+        let [big_y, x, y] = xyy;
+        let big_x = x * big_y / y;
+        let big_z = (1.0 - x - y) * big_y / y;
+        Color::new(
+            (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+            (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+            (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+        )
+    }
+
+    fn in_sun(&self, direction: &Vec3) -> bool {
+        Vec3::dot(*direction, self.sun) >= self.cos_sun_max
+    }
+}
+
+impl Environment for PhysicalSky {
+    fn value(&self, direction: &Vec3) -> Color {
+        let d = direction.unit();
+        if d.y < 0.0 {
+            return self.ground;
+        }
+        let sky = self.sky_radiance(&d);
+        if self.in_sun(&d) {
+            sky + self.sun_radiance
+        } else {
+            sky
+        }
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    // The table covers the sky and ground, a cone around the sun the disk
+    fn pdf_value(&self, direction: &Vec3) -> f32 {
+        let mut pdf = (1.0 - self.sun_weight) * self.table.pdf_value(direction);
+        if self.sun_weight > 0.0 && self.in_sun(&direction.unit()) {
+            pdf += self.sun_weight / self.solid_angle();
+        }
+        pdf
+    }
+
+    fn random(&self) -> Vec3 {
+        if random_f() < self.sun_weight {
+            let uvw = Onb::build_from_w(&self.sun);
+            let sin_sun_max = (1.0 - self.cos_sun_max * self.cos_sun_max).sqrt();
+            uvw.local(&random_to_sphere(sin_sun_max, 1.0))
+        } else {
+            self.table.random()
+        }
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "PhysicalSky",
+            "sun_direction": vec3_value(&self.sun_direction),
+            "turbidity": number(self.turbidity),
+            "ground_albedo": vec3_value(&self.ground_albedo),
+            "sun_radius": number(self.sun_radius),
+            "intensity": number(self.intensity),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::vec3::random_in_unit_vector;
+
+    fn afternoon() -> PhysicalSky {
+        PhysicalSky::new(
+            Vec3::new(1.0, 1.0, 0.5),
+            3.0,
+            Color::new(0.3, 0.3, 0.3),
+            0.265,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn test_sky_radiance() {
+        let sky = afternoon();
+        let zenith = sky.value(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x);
+        assert!(zenith.y > 0.3 && zenith.y < 1.5);
+
+        // Brighter towards the sun, whose disk outshines everything
+        let opposite = sky.value(&Vec3::new(-1.0, 1.0, -0.5));
+        let near_sun = sky.value(&Vec3::new(1.0, 1.1, 0.5));
+        assert!(luminance(&near_sun) > luminance(&opposite));
+        let sun = sky.value(&Vec3::new(1.0, 1.0, 0.5));
+        assert!(luminance(&sun) > 1000.0 * luminance(&near_sun));
+        assert!(sun.x > sun.z);
+
+        // The ground reflects the light falling on it
+        let ground = sky.value(&Vec3::new(0.0, -1.0, 0.0));
+        assert!(ground.y > 0.0 && ground.y < zenith.y * 10.0);
+
+        // Without a disk there is no sun to hit or sample
+        let no_sun = PhysicalSky::new(Vec3::new(1.0, 1.0, 0.5), 3.0, Color::zero(), 0.0, 1.0);
+        assert_eq!(no_sun.sun_weight, 0.0);
+        assert_eq!(no_sun.value(&Vec3::new(0.0, -1.0, 0.0)), Color::zero());
+    }
+
+    #[test]
+    fn test_sky_sampling() {
+        seed_rng(5);
+        let sky = afternoon();
+        assert!(sky.sun_weight > 0.1 && sky.sun_weight < 0.99);
+
+        // Uniform directions all but miss the sun, so they only see the table's share
+        let n = 200000;
+        let mut integral = 0.0;
+        for _ in 0..n {
+            let v = random_in_unit_vector();
+            integral += sky.pdf_value(&v) * 4.0 * PI / n as f32;
+        }
+        assert!((integral - (1.0 - sky.sun_weight)).abs() < 0.02);
+
+        let mut in_sun = 0;
+        for _ in 0..n {
+            let v = sky.random();
+            assert!(sky.pdf_value(&v) > 0.0);
+            if sky.in_sun(&v.unit()) {
+                in_sun += 1;
+            }
+        }
+        assert!((in_sun as f32 / n as f32 - sky.sun_weight).abs() < 0.01);
+    }
+}
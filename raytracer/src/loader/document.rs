@@ -0,0 +1,620 @@
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::error::LoadError;
+use super::obj::ObjModel;
+use super::SceneDescription;
+pub use crate::base::document::{matrix_value, number, vec3_value};
+use crate::base::{Camera, Color, Mat4, Vec3};
+use crate::bvh::bvh::Bvh;
+use crate::hit::hittable::{FlipFace, RotateY, Translate};
+use crate::hit::transform::{Instances, Transform};
+use crate::hit::{hittable::Hittable, hittable_list::HittableList};
+use crate::light::environment::{EnvironmentMap, UniformEnvironment};
+use crate::light::punctual::{DirectionalLight, PointLight, SpotLight};
+use crate::light::sky::PhysicalSky;
+use crate::light::{Environment, PunctualLight};
+use crate::objects::arrect::{self, XYRect, XZRect, YZRect};
+use crate::objects::constant_medium::ConstantMedium;
+use crate::objects::material::{
+    Conductor, Dielectric, DiffuseLight, Empty, Isotropic, Lambertian, Material, Metal,
+    RoughDielectric,
+};
+use crate::objects::moving_sphere::MovingSphere;
+use crate::objects::principled::{self, Principled};
+use crate::objects::sphere::Sphere;
+use crate::objects::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use crate::objects::triangle::{MeshData, MeshFace, Triangle, TriangleMesh};
+use std::path::Path;
+
+#[derive(Clone)]
+pub struct Node<'a> {
+    pub value: &'a Value,
+    pub path: String,
+}
+
+impl<'a> Node<'a> {
+    pub fn root(value: &'a Value) -> Node<'a> {
+        Node {
+            value,
+            path: String::from("$"),
+        }
+    }
+
+    pub fn opt_field(&self, name: &str) -> Option<Node<'a>> {
+        self.value.get(name).map(|value| Node {
+            value,
+            path: format!("{}.{}", self.path, name),
+        })
+    }
+
+    pub fn field(&self, name: &str) -> Result<Node<'a>, LoadError> {
+        if !self.value.is_object() {
+            return Err(self.invalid("an object"));
+        }
+        self.opt_field(name).ok_or_else(|| LoadError::MissingField {
+            path: self.path.clone(),
+            field: name.to_string(),
+        })
+    }
+
+    pub fn items(&self) -> Result<Vec<Node<'a>>, LoadError> {
+        match self.value.as_array() {
+            Some(array) => Ok(array
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Node {
+                    value,
+                    path: format!("{}[{}]", self.path, i),
+                })
+                .collect()),
+            None => Err(self.invalid("an array")),
+        }
+    }
+
+    pub fn type_name(&self) -> Result<&'a str, LoadError> {
+        self.field("type")?.as_str()
+    }
+
+    pub fn as_str(&self) -> Result<&'a str, LoadError> {
+        self.value.as_str().ok_or_else(|| self.invalid("a string"))
+    }
+
+    pub fn as_f32(&self) -> Result<f32, LoadError> {
+        match self.value.as_f64() {
+            Some(x) => Ok(x as f32),
+            None => Err(self.invalid("a number")),
+        }
+    }
+
+    pub fn as_vec3(&self) -> Result<Vec3, LoadError> {
+        Ok(Vec3::new(
+            self.field("x")?.as_f32()?,
+            self.field("y")?.as_f32()?,
+            self.field("z")?.as_f32()?,
+        ))
+    }
+
+    pub fn as_uv(&self) -> Result<(f32, f32), LoadError> {
+        Ok((self.field("u")?.as_f32()?, self.field("v")?.as_f32()?))
+    }
+
+    pub fn as_index3(&self) -> Result<[usize; 3], LoadError> {
+        let items = self.items()?;
+        if items.len() != 3 {
+            return Err(self.invalid("three indices"));
+        }
+        let mut indices = [0; 3];
+        for (index, item) in indices.iter_mut().zip(&items) {
+            *index = item
+                .value
+                .as_u64()
+                .ok_or_else(|| item.invalid("a vertex index"))? as usize;
+        }
+        Ok(indices)
+    }
+
+    pub fn bool_or(&self, name: &str, default: bool) -> Result<bool, LoadError> {
+        match self.opt_field(name) {
+            Some(node) => node
+                .value
+                .as_bool()
+                .ok_or_else(|| node.invalid("a boolean")),
+            None => Ok(default),
+        }
+    }
+
+    pub fn f32_or(&self, name: &str, default: f32) -> Result<f32, LoadError> {
+        match self.opt_field(name) {
+            Some(node) => node.as_f32(),
+            None => Ok(default),
+        }
+    }
+
+    pub fn invalid(&self, expected: &str) -> LoadError {
+        LoadError::InvalidValue {
+            path: self.path.clone(),
+            expected: expected.to_string(),
+        }
+    }
+
+    pub fn unknown(&self, kind: &str, name: &str) -> LoadError {
+        LoadError::UnknownType {
+            path: format!("{}.type", self.path),
+            kind: kind.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+pub fn build_scene(root: &Value) -> Result<SceneDescription, LoadError> {
+    let root = Node::root(root);
+    let objects = root.field("objects")?;
+    let world = match objects.type_name()? {
+        "HitableList" | "HittableList" => build_list(&objects)?,
+        _ => {
+            let mut world = HittableList::new();
+            world.add(build_hittable(&objects)?);
+            world
+        }
+    };
+    let camera = root.field("camera")?;
+    let aspect_ratio = camera.field("aspect")?.as_f32()?;
+    let environment = match root.opt_field("background") {
+        Some(node) => build_environment(&node)?,
+        None => Arc::new(UniformEnvironment::new(Color::zero())),
+    };
+    let mut lights = Vec::new();
+    if let Some(node) = root.opt_field("lights") {
+        for item in node.items()? {
+            lights.push(build_punctual_light(&item)?);
+        }
+    }
+
+    Ok(SceneDescription {
+        world,
+        camera: build_camera(&camera)?,
+        aspect_ratio,
+        environment,
+        lights,
+    })
+}
+
+// A plain colour, or an object naming the kind of environment
+pub fn build_environment(node: &Node) -> Result<Arc<dyn Environment>, LoadError> {
+    if node.opt_field("type").is_none() {
+        return Ok(Arc::new(UniformEnvironment::new(node.as_vec3()?)));
+    }
+    let name = node.type_name()?;
+    let environment: Arc<dyn Environment> = match name {
+        "EnvironmentMap" => {
+            let file = node.field("file")?.as_str()?;
+            if !Path::new(file).is_file() {
+                return Err(LoadError::Io {
+                    file: file.to_string(),
+                    message: format!("image referenced at {} not found", node.path),
+                });
+            }
+            let map = EnvironmentMap::load(
+                file,
+                node.f32_or("rotation", 0.0)?,
+                node.f32_or("intensity", 1.0)?,
+            )
+            .map_err(|e| LoadError::Io {
+                file: file.to_string(),
+                message: e.to_string(),
+            })?;
+            Arc::new(map)
+        }
+        "PhysicalSky" => Arc::new(PhysicalSky::new(
+            node.field("sun_direction")?.as_vec3()?,
+            node.f32_or("turbidity", 3.0)?,
+            match node.opt_field("ground_albedo") {
+                Some(albedo) => albedo.as_vec3()?,
+                None => Color::new(0.3, 0.3, 0.3),
+            },
+            node.f32_or("sun_radius", 0.265)?,
+            node.f32_or("intensity", 1.0)?,
+        )),
+        _ => return Err(node.unknown("environment", name)),
+    };
+    Ok(environment)
+}
+
+pub fn build_punctual_light(node: &Node) -> Result<Arc<dyn PunctualLight>, LoadError> {
+    let name = node.type_name()?;
+    let range = match node.opt_field("range") {
+        Some(range) => Some(range.as_f32()?),
+        None => None,
+    };
+    let light: Arc<dyn PunctualLight> = match name {
+        "PointLight" => Arc::new(PointLight::new(
+            node.field("position")?.as_vec3()?,
+            node.field("intensity")?.as_vec3()?,
+            range,
+        )),
+        "SpotLight" => Arc::new(SpotLight::new(
+            PointLight::new(
+                node.field("position")?.as_vec3()?,
+                node.field("intensity")?.as_vec3()?,
+                range,
+            ),
+            node.field("direction")?.as_vec3()?,
+            node.field("inner_angle")?.as_f32()?,
+            node.field("outer_angle")?.as_f32()?,
+        )),
+        "DirectionalLight" => Arc::new(DirectionalLight::new(
+            node.field("direction")?.as_vec3()?,
+            node.field("irradiance")?.as_vec3()?,
+            node.f32_or("angular_radius", 0.0)?,
+        )),
+        _ => return Err(node.unknown("light", name)),
+    };
+    Ok(light)
+}
+
+pub fn build_camera(node: &Node) -> Result<Camera, LoadError> {
+    Ok(Camera::new(
+        node.field("look_from")?.as_vec3()?,
+        node.field("look_at")?.as_vec3()?,
+        node.field("vup")?.as_vec3()?,
+        node.field("vfov")?.as_f32()?,
+        node.field("aspect")?.as_f32()?,
+        node.field("aperture")?.as_f32()?,
+        node.field("focus_dist")?.as_f32()?,
+        node.f32_or("time0", 0.0)?,
+        node.f32_or("time1", 1.0)?,
+    ))
+}
+
+fn build_list(node: &Node) -> Result<HittableList, LoadError> {
+    let mut list = HittableList::new();
+    for item in node.field("items")?.items()? {
+        list.add(build_hittable(&item)?);
+    }
+    Ok(list)
+}
+
+pub fn build_hittable(node: &Node) -> Result<Arc<dyn Hittable>, LoadError> {
+    let name = node.type_name()?;
+    let object: Arc<dyn Hittable> = match name {
+        "HitableList" | "HittableList" => Arc::new(build_list(node)?),
+        "BVH" | "Bvh" => Arc::new(Bvh::new(build_list(node)?.objects, 0.0, 1.0)),
+        // Older documents store the binary tree itself; rebuild it from its leaves
+        "BVHNode" | "BvhNode" => {
+            let mut objects = Vec::new();
+            collect_bvh_leaves(node, &mut objects)?;
+            Arc::new(Bvh::new(objects, 0.0, 1.0))
+        }
+        "Sphere" => Arc::new(Sphere::new(
+            &node.field("center")?.as_vec3()?,
+            node.field("radius")?.as_f32()?,
+            build_material(&node.field("material")?)?,
+        )),
+        "MovingSphere" => Arc::new(MovingSphere::new(
+            &node.field("center0")?.as_vec3()?,
+            &node.field("center1")?.as_vec3()?,
+            node.field("time0")?.as_f32()?,
+            node.field("time1")?.as_f32()?,
+            node.field("radius")?.as_f32()?,
+            build_material(&node.field("material")?)?,
+        )),
+        "XYRect" => Arc::new(XYRect::new(
+            node.field("x0")?.as_f32()?,
+            node.field("x1")?.as_f32()?,
+            node.field("y0")?.as_f32()?,
+            node.field("y1")?.as_f32()?,
+            node.field("k")?.as_f32()?,
+            build_material(&node.field("material")?)?,
+        )),
+        "XZRect" => Arc::new(XZRect::new(
+            node.field("x0")?.as_f32()?,
+            node.field("x1")?.as_f32()?,
+            node.field("z0")?.as_f32()?,
+            node.field("z1")?.as_f32()?,
+            node.field("k")?.as_f32()?,
+            build_material(&node.field("material")?)?,
+        )),
+        "YZRect" => Arc::new(YZRect::new(
+            node.field("y0")?.as_f32()?,
+            node.field("y1")?.as_f32()?,
+            node.field("z0")?.as_f32()?,
+            node.field("z1")?.as_f32()?,
+            node.field("k")?.as_f32()?,
+            build_material(&node.field("material")?)?,
+        )),
+        "Box" => Arc::new(arrect::Box::new(
+            node.field("p0")?.as_vec3()?,
+            node.field("p1")?.as_vec3()?,
+            build_material(&node.field("material")?)?,
+        )),
+        "Translate" => Arc::new(Translate::new(
+            build_hittable(&node.field("object")?)?,
+            node.field("offset")?.as_vec3()?,
+        )),
+        "RotateY" => Arc::new(RotateY::new(
+            build_hittable(&node.field("object")?)?,
+            node.field("angle")?.as_f32()?,
+        )),
+        "FlipFace" => Arc::new(FlipFace::new(build_hittable(&node.field("object")?)?)),
+        "Transform" => Arc::new(Transform::new(
+            build_hittable(&node.field("object")?)?,
+            build_transform(&node.field("transform")?)?,
+        )),
+        "Instances" => Arc::new(Instances::new(
+            build_hittable(&node.field("object")?)?,
+            list_of(node, "transforms", build_transform)?,
+        )),
+        "ConstantMedium" => Arc::new(ConstantMedium::new(
+            build_hittable(&node.field("boundary")?)?,
+            node.field("density")?.as_f32()?,
+            build_material(&node.field("phase_function")?)?,
+        )),
+        "Triangle" => {
+            let mut triangle = Triangle::new(
+                node.field("v0")?.as_vec3()?,
+                node.field("v1")?.as_vec3()?,
+                node.field("v2")?.as_vec3()?,
+                build_material(&node.field("material")?)?,
+            );
+            if let Some(normals) = node.opt_field("normals") {
+                triangle = triangle.with_normals(three(&normals, Node::as_vec3)?);
+            }
+            if let Some(uvs) = node.opt_field("uvs") {
+                triangle = triangle.with_uvs(three(&uvs, Node::as_uv)?);
+            }
+            Arc::new(triangle)
+        }
+        "TriangleMesh" => Arc::new(TriangleMesh::new(
+            build_mesh(node)?,
+            build_material(&node.field("material")?)?,
+        )),
+        "ObjModel" => {
+            let material = match node.opt_field("material") {
+                Some(material) => Some(build_material(&material)?),
+                None => None,
+            };
+            Arc::new(ObjModel::load(node.field("file")?.as_str()?, material)?)
+        }
+        _ => return Err(node.unknown("object", name)),
+    };
+    Ok(object)
+}
+
+fn collect_bvh_leaves(node: &Node, objects: &mut Vec<Arc<dyn Hittable>>) -> Result<(), LoadError> {
+    if !matches!(node.type_name()?, "BVHNode" | "BvhNode") {
+        objects.push(build_hittable(node)?);
+        return Ok(());
+    }
+    let left = node.field("left")?;
+    let right = node.field("right")?;
+    collect_bvh_leaves(&left, objects)?;
+    // Single-object nodes repeat their object on both sides
+    if right.value != left.value {
+        collect_bvh_leaves(&right, objects)?;
+    }
+    Ok(())
+}
+
+fn three<'a, T>(
+    node: &Node<'a>,
+    parse: fn(&Node<'a>) -> Result<T, LoadError>,
+) -> Result<[T; 3], LoadError> {
+    let items = node.items()?;
+    if items.len() != 3 {
+        return Err(node.invalid("three entries"));
+    }
+    Ok([parse(&items[0])?, parse(&items[1])?, parse(&items[2])?])
+}
+
+fn list_of<'a, T>(
+    node: &Node<'a>,
+    name: &str,
+    parse: fn(&Node<'a>) -> Result<T, LoadError>,
+) -> Result<Vec<T>, LoadError> {
+    match node.opt_field(name) {
+        Some(list) => list.items()?.iter().map(parse).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+// A list of steps applied first to last; each step is one of
+// {"translate": v}, {"scale": v or s}, {"rotate": {"axis": v, "angle": degrees}}
+// or {"matrix": four rows of four numbers}
+fn build_transform(node: &Node) -> Result<Mat4, LoadError> {
+    let mut matrix = Mat4::identity();
+    for step in node.items()? {
+        let m = if let Some(offset) = step.opt_field("translate") {
+            Mat4::translate(offset.as_vec3()?)
+        } else if let Some(scale) = step.opt_field("scale") {
+            match scale.as_f32() {
+                Ok(s) => Mat4::scale(Vec3::new(s, s, s)),
+                Err(_) => Mat4::scale(scale.as_vec3()?),
+            }
+        } else if let Some(rotate) = step.opt_field("rotate") {
+            Mat4::rotate(
+                rotate.field("axis")?.as_vec3()?,
+                rotate.field("angle")?.as_f32()?,
+            )
+        } else if let Some(rows) = step.opt_field("matrix") {
+            let rows = rows.items()?;
+            if rows.len() != 4 {
+                return Err(step.invalid("a 4x4 matrix"));
+            }
+            let mut m = Mat4::identity();
+            for (i, row) in rows.iter().enumerate() {
+                let row = row.items()?;
+                if row.len() != 4 {
+                    return Err(step.invalid("a 4x4 matrix"));
+                }
+                for (j, x) in row.iter().enumerate() {
+                    m.m[i][j] = x.as_f32()?;
+                }
+            }
+            m
+        } else {
+            return Err(step.invalid("a translate, scale, rotate or matrix step"));
+        };
+        matrix = m * matrix;
+    }
+    if matrix.inverse().is_none() {
+        return Err(node.invalid("an invertible transform"));
+    }
+    Ok(matrix)
+}
+
+fn build_mesh(node: &Node) -> Result<MeshData, LoadError> {
+    let data = MeshData {
+        positions: list_of(node, "positions", Node::as_vec3)?,
+        normals: list_of(node, "normals", Node::as_vec3)?,
+        uvs: list_of(node, "uvs", Node::as_uv)?,
+        faces: node
+            .field("faces")?
+            .items()?
+            .iter()
+            .map(|face| {
+                Ok(MeshFace {
+                    vertices: face.field("vertices")?.as_index3()?,
+                    normals: face
+                        .opt_field("normals")
+                        .map(|n| n.as_index3())
+                        .transpose()?,
+                    uvs: face.opt_field("uvs").map(|n| n.as_index3()).transpose()?,
+                })
+            })
+            .collect::<Result<_, LoadError>>()?,
+    };
+
+    for (i, face) in data.faces.iter().enumerate() {
+        let in_range = |indices: Option<[usize; 3]>, len: usize| {
+            indices.map_or(true, |ix| ix.iter().all(|&k| k < len))
+        };
+        if !in_range(Some(face.vertices), data.positions.len())
+            || !in_range(face.normals, data.normals.len())
+            || !in_range(face.uvs, data.uvs.len())
+        {
+            return Err(LoadError::InvalidValue {
+                path: format!("{}.faces[{}]", node.path, i),
+                expected: String::from("indices within the vertex arrays"),
+            });
+        }
+    }
+    Ok(data)
+}
+
+pub fn build_material(node: &Node) -> Result<Arc<dyn Material>, LoadError> {
+    let name = node.type_name()?;
+    let material: Arc<dyn Material> = match name {
+        "Lambertian" => Arc::new(Lambertian::new(build_texture(&node.field("albedo")?)?)),
+        "Metal" => Arc::new(Metal::new(
+            node.field("albedo")?.as_vec3()?,
+            node.field("fuzz")?.as_f32()?,
+        )),
+        "Dielectric" => Arc::new(Dielectric::new(node.field("ref_idx")?.as_f32()?)),
+        "DiffuseLight" => Arc::new(build_diffuse_light(node)?),
+        "Isotropic" => Arc::new(Isotropic::new(build_texture(&node.field("albedo")?)?)),
+        "Empty" => Arc::new(Empty::new()),
+        "Principled" => Arc::new(build_principled(node)?),
+        "Conductor" => {
+            let roughness = node.f32_or("roughness", 0.0)?;
+            let conductor = match node.opt_field("preset") {
+                Some(preset) => {
+                    Conductor::preset(preset.as_str()?, roughness).ok_or_else(|| {
+                        let names: Vec<_> = Conductor::preset_names().collect();
+                        preset.invalid(&format!("one of {}", names.join(", ")))
+                    })?
+                }
+                None => Conductor::new(
+                    node.field("eta")?.as_vec3()?,
+                    node.field("k")?.as_vec3()?,
+                    roughness,
+                ),
+            };
+            Arc::new(conductor)
+        }
+        "RoughDielectric" => Arc::new(RoughDielectric::new(
+            node.field("ref_idx")?.as_f32()?,
+            node.f32_or("roughness", 0.0)?,
+        )),
+        _ => return Err(node.unknown("material", name)),
+    };
+    Ok(material)
+}
+
+// Every parameter but the index of refraction takes a number, a color or a texture
+fn build_principled(node: &Node) -> Result<Principled, LoadError> {
+    let param = |name: &str, default: Arc<dyn Texture>| -> Result<Arc<dyn Texture>, LoadError> {
+        let field = match node.opt_field(name) {
+            Some(field) => field,
+            None => return Ok(default),
+        };
+        if let Ok(x) = field.as_f32() {
+            return Ok(principled::constant(x));
+        }
+        if field.opt_field("type").is_some() {
+            return build_texture(&field);
+        }
+        Ok(Arc::new(SolidColor::new_with_color(field.as_vec3()?)))
+    };
+    let mut material = Principled::new(param("base_color", principled::constant(0.8))?);
+    material.metallic = param("metallic", material.metallic)?;
+    material.roughness = param("roughness", material.roughness)?;
+    material.specular = param("specular", material.specular)?;
+    material.specular_tint = param("specular_tint", material.specular_tint)?;
+    material.sheen = param("sheen", material.sheen)?;
+    material.sheen_tint = param("sheen_tint", material.sheen_tint)?;
+    material.clearcoat = param("clearcoat", material.clearcoat)?;
+    material.clearcoat_gloss = param("clearcoat_gloss", material.clearcoat_gloss)?;
+    material.transmission = param("transmission", material.transmission)?;
+    material.anisotropic = param("anisotropic", material.anisotropic)?;
+    material.ior = node.f32_or("ior", material.ior)?;
+    Ok(material)
+}
+
+// Strength is given as an intensity, or as watts or lumens over an `area`
+fn build_diffuse_light(node: &Node) -> Result<DiffuseLight<Arc<dyn Texture>>, LoadError> {
+    let mut light = DiffuseLight::new(build_texture(&node.field("emit")?)?);
+    if node.bool_or("two_sided", false)? {
+        light = light.two_sided();
+    }
+    if let Some(power) = node.opt_field("power") {
+        light = light.with_power(power.as_f32()?, node.field("area")?.as_f32()?);
+    } else if let Some(lumens) = node.opt_field("lumens") {
+        light = light.with_lumens(lumens.as_f32()?, node.field("area")?.as_f32()?);
+    } else {
+        light = light.with_intensity(node.f32_or("intensity", 1.0)?);
+    }
+    if let Some(temperature) = node.opt_field("temperature") {
+        light = light.with_temperature(temperature.as_f32()?);
+    }
+    if let Some(profile) = node.opt_field("profile") {
+        light = light.with_profile(build_texture(&profile)?);
+    }
+    Ok(light)
+}
+
+pub fn build_texture(node: &Node) -> Result<Arc<dyn Texture>, LoadError> {
+    let name = node.type_name()?;
+    let texture: Arc<dyn Texture> = match name {
+        "ConstantTexture" | "SolidColor" => {
+            Arc::new(SolidColor::new_with_color(node.field("color")?.as_vec3()?))
+        }
+        "CheckerTexture" => Arc::new(CheckerTexture::new(
+            build_texture(&node.field("t0")?)?,
+            build_texture(&node.field("t1")?)?,
+        )),
+        "NoiseTexture" => Arc::new(NoiseTexture::new(node.field("scale")?.as_f32()?)),
+        "ImageTexture" => {
+            let file = node.field("file")?.as_str()?;
+            if !Path::new(file).is_file() {
+                return Err(LoadError::Io {
+                    file: file.to_string(),
+                    message: format!("image referenced at {} not found", node.path),
+                });
+            }
+            Arc::new(ImageTexture::new(file))
+        }
+        _ => return Err(node.unknown("texture", name)),
+    };
+    Ok(texture)
+}
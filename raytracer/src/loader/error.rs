@@ -0,0 +1,51 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    Io {
+        file: String,
+        message: String,
+    },
+    Syntax {
+        message: String,
+    },
+    MissingField {
+        path: String,
+        field: String,
+    },
+    UnknownType {
+        path: String,
+        kind: String,
+        name: String,
+    },
+    InvalidValue {
+        path: String,
+        expected: String,
+    },
+    Unsupported {
+        path: String,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { file, message } => write!(f, "cannot read {}: {}", file, message),
+            LoadError::Syntax { message } => write!(f, "syntax error: {}", message),
+            LoadError::MissingField { path, field } => {
+                write!(f, "missing field `{}` at {}", field, path)
+            }
+            LoadError::UnknownType { path, kind, name } => {
+                write!(f, "unknown {} type `{}` at {}", kind, name, path)
+            }
+            LoadError::InvalidValue { path, expected } => {
+                write!(f, "expected {} at {}", expected, path)
+            }
+            LoadError::Unsupported { path } => {
+                write!(f, "the object at {} cannot be serialized", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
@@ -0,0 +1,98 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+use super::document::{number, vec3_value};
+use super::error::LoadError;
+use super::SceneDescription;
+use crate::base::Camera;
+
+pub fn camera_to_value(camera: &Camera) -> Value {
+    json!({
+        "look_from": vec3_value(&camera.orig),
+        "look_at": vec3_value(&camera.lookat),
+        "vup": vec3_value(&camera.vup),
+        "vfov": number(camera.vfov),
+        "aspect": number(camera.aspect_ratio),
+        "aperture": number(camera.lens_radius * 2.0),
+        "focus_dist": number(camera.focus_dist),
+        "time0": number(camera.time0),
+        "time1": number(camera.time1),
+    })
+}
+
+pub fn scene_to_value(scene: &SceneDescription) -> Result<Value, LoadError> {
+    let mut items = Vec::new();
+    for (i, object) in scene.world.objects.iter().enumerate() {
+        match object.to_document() {
+            Some(item) => items.push(item),
+            None => {
+                return Err(LoadError::Unsupported {
+                    path: format!("$.objects.items[{}]", i),
+                })
+            }
+        }
+    }
+
+    let background = scene
+        .environment
+        .to_document()
+        .ok_or_else(|| LoadError::Unsupported {
+            path: String::from("$.background"),
+        })?;
+
+    let mut lights = Vec::new();
+    for (i, light) in scene.lights.iter().enumerate() {
+        match light.to_document() {
+            Some(item) => lights.push(item),
+            None => {
+                return Err(LoadError::Unsupported {
+                    path: format!("$.lights[{}]", i),
+                })
+            }
+        }
+    }
+
+    let mut root = json!({
+        "objects": {
+            "type": "HitableList",
+            "items": items,
+        },
+        "camera": camera_to_value(&scene.camera),
+        "background": background,
+    });
+    // Scenes lit only by their objects keep the shorter document
+    if !lights.is_empty() {
+        root["lights"] = Value::Array(lights);
+    }
+    Ok(root)
+}
+
+pub fn to_json(scene: &SceneDescription) -> Result<String, LoadError> {
+    let value = scene_to_value(scene)?;
+    let mut buf = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    serde::Serialize::serialize(&value, &mut ser).map_err(|e| LoadError::Syntax {
+        message: e.to_string(),
+    })?;
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+pub fn to_yaml(scene: &SceneDescription) -> Result<String, LoadError> {
+    let text = serde_yaml::to_string(&scene_to_value(scene)?).map_err(|e| LoadError::Syntax {
+        message: e.to_string(),
+    })?;
+    Ok(text.trim_start_matches("---\n").to_string())
+}
+
+pub fn save_scene(scene: &SceneDescription, file: &str) -> Result<(), LoadError> {
+    let text = match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => to_yaml(scene)?,
+        _ => to_json(scene)?,
+    };
+    fs::write(file, text).map_err(|e| LoadError::Io {
+        file: file.to_string(),
+        message: e.to_string(),
+    })
+}
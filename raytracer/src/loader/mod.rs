@@ -0,0 +1,354 @@
+pub mod document;
+pub mod error;
+pub mod export;
+pub mod json;
+pub mod obj;
+pub mod yaml;
+
+pub use self::error::LoadError;
+pub use self::export::save_scene;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::base::{Camera, Color};
+use crate::hit::hittable_list::HittableList;
+use crate::light::{Environment, PunctualLight};
+
+pub struct SceneDescription {
+    pub world: HittableList,
+    pub camera: Camera,
+    pub aspect_ratio: f32,
+    pub environment: Arc<dyn Environment>,
+    pub lights: Vec<Arc<dyn PunctualLight>>,
+}
+
+pub fn load_scene(file: &str) -> Result<SceneDescription, LoadError> {
+    match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => yaml::load(file),
+        _ => json::load(file),
+    }
+}
+
+fn read_file(file: &str) -> Result<String, LoadError> {
+    fs::read_to_string(file).map_err(|e| LoadError::Io {
+        file: file.to_string(),
+        message: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::Point3;
+    use crate::hit::hittable::Hittable;
+    use crate::light::environment::UniformEnvironment;
+
+    fn data(name: &str) -> String {
+        format!("{}/../data/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_load_json_scenes() {
+        for name in &["scene_10.json", "scene_200_no_bvh.json", "scene_500.json"] {
+            let scene = load_scene(&data(name)).unwrap();
+            assert!(!scene.world.objects.is_empty());
+            assert_eq!(scene.aspect_ratio, 1.0);
+            assert_eq!(scene.camera.orig, Point3::new(-6.0, 2.0, -6.0));
+        }
+    }
+
+    #[test]
+    fn test_load_yaml_scenes() {
+        for name in &["scene_10.yaml", "scene_200_no_bvh.yaml", "scene_500.yaml"] {
+            let scene = load_scene(&data(name)).unwrap();
+            assert!(!scene.world.objects.is_empty());
+            assert_eq!(scene.camera.orig, Point3::new(-6.0, 2.0, -6.0));
+        }
+    }
+
+    #[test]
+    fn test_json_yaml_equivalent() {
+        for name in &["scene_10", "scene_200_no_bvh", "scene_500"] {
+            let json_doc = json::to_value(&read_file(&data(&format!("{}.json", name))).unwrap());
+            let yaml_doc = yaml::to_value(&read_file(&data(&format!("{}.yaml", name))).unwrap());
+            assert_eq!(json_doc.unwrap(), yaml_doc.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        for build in &[crate::scene::random_scene, crate::scene::cornell_smoke] {
+            let scene = SceneDescription {
+                world: build(),
+                camera: Camera::new(
+                    Point3::new(13.0, 2.0, 3.0),
+                    Point3::zero(),
+                    Point3::new(0.0, 1.0, 0.0),
+                    20.0,
+                    1.5,
+                    0.1,
+                    10.0,
+                    0.0,
+                    1.0,
+                ),
+                aspect_ratio: 1.5,
+                environment: Arc::new(UniformEnvironment::new(Color::new(0.7, 0.8, 1.0))),
+                lights: Vec::new(),
+            };
+            let text = export::to_json(&scene).unwrap();
+            let reloaded = json::from_str(&text).unwrap();
+            assert_eq!(export::to_json(&reloaded).unwrap(), text);
+            assert_eq!(reloaded.aspect_ratio, 1.5);
+
+            let text = export::to_yaml(&scene).unwrap();
+            let reloaded = yaml::from_str(&text).unwrap();
+            assert_eq!(export::to_yaml(&reloaded).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_lighting_round_trip() {
+        let file = format!("{}/../pictures/background.jpg", env!("CARGO_MANIFEST_DIR"));
+        let text = format!(
+            r#"{{
+            "objects": {{"type": "HitableList", "items": []}},
+            "camera": {{"look_from": {{"x": 0, "y": 0, "z": 5}}, "look_at": {{"x": 0, "y": 0, "z": 0}},
+                       "vup": {{"x": 0, "y": 1, "z": 0}}, "vfov": 40, "aspect": 1, "aperture": 0,
+                       "focus_dist": 5}},
+            "background": {{"type": "EnvironmentMap", "file": "{}", "rotation": 90, "intensity": 2}}
+        }}"#,
+            file
+        );
+        let scene = json::from_str(&text).unwrap();
+        assert!(scene.environment.is_sampled());
+        let exported = export::to_json(&scene).unwrap();
+        assert!(exported.contains(r#""rotation": 90"#));
+        assert_eq!(
+            export::to_json(&json::from_str(&exported).unwrap()).unwrap(),
+            exported
+        );
+
+        let sky = text.replace(
+            &format!(r#""file": "{}""#, file),
+            r#""sun_direction": {"x": 1, "y": 1, "z": 0}"#,
+        );
+        let sky = sky.replace("EnvironmentMap", "PhysicalSky");
+        let exported = export::to_json(&json::from_str(&sky).unwrap()).unwrap();
+        assert!(exported.contains(r#""turbidity": 3"#));
+        assert_eq!(
+            export::to_json(&json::from_str(&exported).unwrap()).unwrap(),
+            exported
+        );
+
+        let lit = sky.replace(
+            r#""background""#,
+            r#""lights": [
+                {"type": "PointLight", "position": {"x": 0, "y": 3, "z": 0},
+                 "intensity": {"x": 10, "y": 10, "z": 10}, "range": 20},
+                {"type": "SpotLight", "position": {"x": 0, "y": 3, "z": 0}, "direction": {"x": 0, "y": -1, "z": 0},
+                 "intensity": {"x": 10, "y": 10, "z": 10}, "inner_angle": 15, "outer_angle": 25},
+                {"type": "DirectionalLight", "direction": {"x": 1, "y": 2, "z": 0},
+                 "irradiance": {"x": 3, "y": 3, "z": 3}, "angular_radius": 0.5}
+            ],
+            "background""#,
+        );
+        let lit = lit.replace(
+            r#""items": []"#,
+            r#""items": [
+                {"type": "Sphere", "center": {"x": 0, "y": 3, "z": 0}, "radius": 0.5,
+                 "material": {"type": "DiffuseLight", "two_sided": true, "power": 100, "area": 3.14,
+                              "temperature": 3200,
+                              "emit": {"type": "ConstantTexture", "color": {"x": 1, "y": 1, "z": 1}},
+                              "profile": {"type": "ConstantTexture", "color": {"x": 0.5, "y": 0.5, "z": 0.5}}}}
+            ]"#,
+        );
+        let scene = json::from_str(&lit).unwrap();
+        assert_eq!(scene.lights.len(), 3);
+        assert_eq!(scene.world.lights().objects.len(), 1);
+        let exported = export::to_json(&scene).unwrap();
+        assert_eq!(
+            export::to_json(&json::from_str(&exported).unwrap()).unwrap(),
+            exported
+        );
+
+        let missing = text.replace("background.jpg", "missing.jpg");
+        assert!(matches!(
+            json::from_str(&missing).err(),
+            Some(LoadError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mesh_round_trip() {
+        let text = r#"{
+            "objects": {"type": "HitableList", "items": [
+                {"type": "TriangleMesh",
+                 "positions": [{"x": 0, "y": 0, "z": 0}, {"x": 1, "y": 0, "z": 0},
+                               {"x": 0, "y": 1, "z": 0}, {"x": 1, "y": 1, "z": 0}],
+                 "uvs": [{"u": 0, "v": 0}, {"u": 1, "v": 1}],
+                 "faces": [{"vertices": [0, 1, 2]}, {"vertices": [1, 3, 2], "uvs": [0, 1, 1]}],
+                 "material": {"type": "Lambertian",
+                              "albedo": {"type": "ConstantTexture", "color": {"x": 1, "y": 0, "z": 0}}}},
+                {"type": "Triangle",
+                 "v0": {"x": 0, "y": 0, "z": 1}, "v1": {"x": 1, "y": 0, "z": 1}, "v2": {"x": 0, "y": 1, "z": 1},
+                 "normals": [{"x": 0, "y": 0, "z": 1}, {"x": 0, "y": 0, "z": 1}, {"x": 0, "y": 0.6, "z": 0.8}],
+                 "material": {"type": "Dielectric", "ref_idx": 1.5}}
+            ]},
+            "camera": {"look_from": {"x": 0, "y": 0, "z": 5}, "look_at": {"x": 0, "y": 0, "z": 0},
+                       "vup": {"x": 0, "y": 1, "z": 0}, "vfov": 40, "aspect": 1, "aperture": 0,
+                       "focus_dist": 5}
+        }"#;
+        let scene = json::from_str(text).unwrap();
+        let exported = export::to_json(&scene).unwrap();
+        assert_eq!(
+            export::to_json(&json::from_str(&exported).unwrap()).unwrap(),
+            exported
+        );
+
+        let bad = text.replace("[1, 3, 2]", "[1, 4, 2]");
+        assert_eq!(
+            json::from_str(&bad).err(),
+            Some(LoadError::InvalidValue {
+                path: String::from("$.objects.items[0].faces[1]"),
+                expected: String::from("indices within the vertex arrays"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_transform_round_trip() {
+        let text = r#"{
+            "objects": {"type": "HitableList", "items": [
+                {"type": "Instances",
+                 "object": {"type": "Box", "p0": {"x": 0, "y": 0, "z": 0}, "p1": {"x": 1, "y": 1, "z": 1},
+                            "material": {"type": "Metal", "albedo": {"x": 0.8, "y": 0.8, "z": 0.8}, "fuzz": 0}},
+                 "transforms": [
+                    [{"scale": 2}, {"rotate": {"axis": {"x": 1, "y": 0, "z": 0}, "angle": 45}}],
+                    [{"translate": {"x": 3, "y": 0, "z": 0}}]
+                 ]},
+                {"type": "Transform",
+                 "transform": [{"scale": {"x": 1, "y": 2, "z": 1}}, {"translate": {"x": 0, "y": 0, "z": -4}}],
+                 "object": {"type": "Sphere", "center": {"x": 0, "y": 0, "z": 0}, "radius": 1,
+                            "material": {"type": "Dielectric", "ref_idx": 1.5}}}
+            ]},
+            "camera": {"look_from": {"x": 0, "y": 0, "z": 5}, "look_at": {"x": 0, "y": 0, "z": 0},
+                       "vup": {"x": 0, "y": 1, "z": 0}, "vfov": 40, "aspect": 1, "aperture": 0,
+                       "focus_dist": 5}
+        }"#;
+        let scene = json::from_str(text).unwrap();
+        let bbox = scene.world.objects[1].bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(bbox.minimum.y, -2.0);
+        assert_eq!(bbox.maximum.z, -3.0);
+        let exported = export::to_json(&scene).unwrap();
+        assert_eq!(
+            export::to_json(&json::from_str(&exported).unwrap()).unwrap(),
+            exported
+        );
+
+        let flat = text.replace(r#""scale": 2"#, r#""scale": 0"#);
+        assert_eq!(
+            json::from_str(&flat).err(),
+            Some(LoadError::InvalidValue {
+                path: String::from("$.objects.items[0].transforms[0]"),
+                expected: String::from("an invertible transform"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_obj() {
+        let objects = format!("{}/../objects", env!("CARGO_MANIFEST_DIR"));
+        let cube = obj::ObjModel::load(&format!("{}/cube.obj", objects), None).unwrap();
+        assert_eq!(cube.meshes.objects.len(), 1);
+        let bbox = cube.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.minimum.x < -0.99 && bbox.maximum.x > 0.99);
+
+        // The body group uses "pat", whose map_Kd is the only texture that ships with it
+        let patrick = obj::ObjModel::load(&format!("{}/patrick.obj", objects), None).unwrap();
+        let doc = patrick.meshes.objects[0].to_document().unwrap();
+        assert_eq!(doc["material"]["albedo"]["type"], "ImageTexture");
+        assert_eq!(doc["faces"].as_array().unwrap().len(), 1164);
+        assert!(doc["faces"][0]["normals"].is_array() && doc["faces"][0]["uvs"].is_array());
+
+        let text =
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 2 0\ng quad\nf 1 2 3 4 5\ng tri\nf -3 -2 -1\n";
+        let model = obj::parse_obj("inline.obj", text).unwrap();
+        assert_eq!(model.groups.len(), 2);
+        assert_eq!(model.groups[0].faces.len(), 3);
+        assert_eq!(
+            model.groups[1].faces[0],
+            [(2, None, None), (3, None, None), (4, None, None)]
+        );
+        assert!(obj::parse_obj("bad.obj", "v 0 0 0\nf 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn test_obj_emissive_groups() {
+        let dir = std::env::temp_dir().join(format!("raytracer-obj-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("lamp.obj");
+        fs::write(
+            &obj_file,
+            "mtllib lamp.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             usemtl shade\nf 1 2 3\nusemtl bulb\nf 1 3 4\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lamp.mtl"),
+            "newmtl shade\nKd 0.5 0.5 0.5\nnewmtl bulb\nKe 4 4 4\n",
+        )
+        .unwrap();
+
+        let text = format!(
+            r#"{{
+            "objects": {{"type": "HitableList", "items": [{{"type": "ObjModel", "file": "{}"}}]}},
+            "camera": {{"look_from": {{"x": 0, "y": 0, "z": 5}}, "look_at": {{"x": 0, "y": 0, "z": 0}},
+                       "vup": {{"x": 0, "y": 1, "z": 0}}, "vfov": 40, "aspect": 1, "aperture": 0,
+                       "focus_dist": 5}}
+        }}"#,
+            obj_file.display()
+        );
+        let scene = json::from_str(&text);
+        fs::remove_dir_all(&dir).unwrap();
+        let lights = scene.unwrap().world.lights();
+        assert_eq!(lights.objects.len(), 1);
+        assert!(lights.objects[0].is_emissive());
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let text = r#"{
+            "objects": {"type": "HitableList", "items": [
+                {"type": "Sphere", "center": {"x": 0, "y": 0, "z": 0},
+                 "material": {"type": "Dielectric", "ref_idx": 1.5}}
+            ]},
+            "camera": {}
+        }"#;
+        assert_eq!(
+            json::from_str(text).err(),
+            Some(LoadError::MissingField {
+                path: String::from("$.objects.items[0]"),
+                field: String::from("radius"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let text = r#"{
+            "objects": {"type": "Sphere", "center": {"x": 0, "y": 0, "z": 0}, "radius": 1,
+                        "material": {"type": "Velvet"}},
+            "camera": {}
+        }"#;
+        assert_eq!(
+            json::from_str(text).err(),
+            Some(LoadError::UnknownType {
+                path: String::from("$.objects.material.type"),
+                kind: String::from("material"),
+                name: String::from("Velvet"),
+            })
+        );
+    }
+}
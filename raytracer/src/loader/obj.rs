@@ -0,0 +1,386 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::error::LoadError;
+use super::read_file;
+use crate::base::{ray::Ray, Color, Point3, Vec3};
+use crate::bvh::aabb::AABB;
+use crate::hit::{hittable::*, hittable_list::HittableList};
+use crate::objects::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::objects::texture::{ImageTexture, SolidColor};
+use crate::objects::triangle::{MeshData, MeshFace, TriangleMesh};
+
+// A model loaded from a Wavefront .obj file, one TriangleMesh per group and material
+#[derive(Clone)]
+pub struct ObjModel {
+    pub file: String,
+    pub material: Option<Arc<dyn Material>>,
+    pub meshes: HittableList,
+}
+
+impl ObjModel {
+    pub fn load(file: &str, material: Option<Arc<dyn Material>>) -> Result<ObjModel, LoadError> {
+        let obj = parse_obj(file, &read_file(file)?)?;
+        let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+
+        let mut libraries = HashMap::new();
+        if material.is_none() {
+            for name in &obj.libraries {
+                let mtl_file = dir.join(name).to_string_lossy().into_owned();
+                libraries.extend(parse_mtl(&mtl_file, &read_file(&mtl_file)?)?);
+            }
+        }
+
+        let mut meshes = HittableList::new();
+        for group in obj.groups.iter().filter(|group| !group.faces.is_empty()) {
+            let mesh_material = match (&material, libraries.get(&group.material)) {
+                (Some(m), _) => m.clone(),
+                (None, Some(mtl)) => mtl.to_material(dir),
+                (None, None) => {
+                    if !group.material.is_empty() {
+                        eprintln!(
+                            "{}: material {} not found, using the default",
+                            file, group.material
+                        );
+                    }
+                    MtlMaterial::default().to_material(dir)
+                }
+            };
+            meshes.add(Arc::new(TriangleMesh::new(
+                obj.mesh_data(group),
+                mesh_material,
+            )));
+        }
+
+        Ok(ObjModel {
+            file: file.to_string(),
+            material,
+            meshes,
+        })
+    }
+}
+
+impl Hittable for ObjModel {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.meshes.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        if self.meshes.objects.is_empty() {
+            return None;
+        }
+        self.meshes.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.meshes.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.meshes.random(o, time)
+    }
+
+    // Groups whose MTL material has Ke are lights of their own
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.meshes.collect_lights(lights)
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut doc = json!({
+            "type": "ObjModel",
+            "file": self.file,
+        });
+        if let Some(material) = &self.material {
+            doc["material"] = material.to_document()?;
+        }
+        Some(doc)
+    }
+}
+
+type Corner = (usize, Option<usize>, Option<usize>);
+
+pub(super) struct Group {
+    pub name: String,
+    pub material: String,
+    pub faces: Vec<[Corner; 3]>,
+}
+
+#[derive(Default)]
+pub(super) struct ObjFile {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    libraries: Vec<String>,
+    pub groups: Vec<Group>,
+}
+
+impl ObjFile {
+    fn group(&mut self, name: &str, material: &str) -> &mut Group {
+        let found = self
+            .groups
+            .iter()
+            .position(|g| g.name == name && g.material == material);
+        let index = match found {
+            Some(index) => index,
+            None => {
+                self.groups.push(Group {
+                    name: name.to_string(),
+                    material: material.to_string(),
+                    faces: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[index]
+    }
+
+    // Copies out only the vertices a group references so each mesh stays compact
+    fn mesh_data(&self, group: &Group) -> MeshData {
+        let mut data = MeshData {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            faces: Vec::with_capacity(group.faces.len()),
+        };
+        let mut position_map = HashMap::new();
+        let mut normal_map = HashMap::new();
+        let mut uv_map = HashMap::new();
+
+        for corners in &group.faces {
+            let mut face = MeshFace {
+                vertices: [0; 3],
+                normals: corners.iter().all(|c| c.2.is_some()).then(|| [0; 3]),
+                uvs: corners.iter().all(|c| c.1.is_some()).then(|| [0; 3]),
+            };
+            for (k, &(v, vt, vn)) in corners.iter().enumerate() {
+                face.vertices[k] =
+                    remap(&mut position_map, &mut data.positions, &self.positions, v);
+                if let (Some(normals), Some(vn)) = (&mut face.normals, vn) {
+                    normals[k] = remap(&mut normal_map, &mut data.normals, &self.normals, vn);
+                }
+                if let (Some(uvs), Some(vt)) = (&mut face.uvs, vt) {
+                    uvs[k] = remap(&mut uv_map, &mut data.uvs, &self.uvs, vt);
+                }
+            }
+            data.faces.push(face);
+        }
+        data
+    }
+}
+
+fn remap<T: Copy>(map: &mut HashMap<usize, usize>, out: &mut Vec<T>, src: &[T], i: usize) -> usize {
+    *map.entry(i).or_insert_with(|| {
+        out.push(src[i]);
+        out.len() - 1
+    })
+}
+
+fn syntax(file: &str, line: usize, message: &str) -> LoadError {
+    LoadError::Syntax {
+        message: format!("{}:{}: {}", file, line, message),
+    }
+}
+
+fn floats(file: &str, line: usize, args: &[&str], count: usize) -> Result<Vec<f32>, LoadError> {
+    if args.len() < count {
+        return Err(syntax(file, line, &format!("expected {} numbers", count)));
+    }
+    args[..count]
+        .iter()
+        .map(|a| {
+            a.parse::<f32>()
+                .map_err(|_| syntax(file, line, &format!("invalid number {}", a)))
+        })
+        .collect()
+}
+
+// OBJ indices are 1-based, negative ones count back from the latest element
+fn index(token: &str, len: usize) -> Option<usize> {
+    let i = token.parse::<i64>().ok()?;
+    let i = if i < 0 { len as i64 + i } else { i - 1 };
+    if i >= 0 && (i as usize) < len {
+        Some(i as usize)
+    } else {
+        None
+    }
+}
+
+pub(super) fn parse_obj(file: &str, text: &str) -> Result<ObjFile, LoadError> {
+    let mut obj = ObjFile::default();
+    let mut group = String::new();
+    let mut material = String::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let n = n + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let p = floats(file, n, &args, 3)?;
+                obj.positions.push(Point3::new(p[0], p[1], p[2]));
+            }
+            "vn" => {
+                let p = floats(file, n, &args, 3)?;
+                obj.normals.push(Vec3::new(p[0], p[1], p[2]));
+            }
+            "vt" => {
+                let p = floats(file, n, &args, 1)?;
+                let v = args.get(1).and_then(|v| v.parse().ok()).unwrap_or(0.0);
+                obj.uvs.push((p[0], v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(syntax(file, n, "a face needs at least three vertices"));
+                }
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in &args {
+                    let mut parts = arg.split('/');
+                    let v = parts
+                        .next()
+                        .and_then(|v| index(v, obj.positions.len()))
+                        .ok_or_else(|| syntax(file, n, &format!("invalid vertex {}", arg)))?;
+                    let vt = match parts.next() {
+                        Some(vt) if !vt.is_empty() => Some(
+                            index(vt, obj.uvs.len())
+                                .ok_or_else(|| syntax(file, n, &format!("invalid uv {}", arg)))?,
+                        ),
+                        _ => None,
+                    };
+                    let vn = match parts.next() {
+                        Some(vn) if !vn.is_empty() => {
+                            Some(index(vn, obj.normals.len()).ok_or_else(|| {
+                                syntax(file, n, &format!("invalid normal {}", arg))
+                            })?)
+                        }
+                        _ => None,
+                    };
+                    corners.push((v, vt, vn));
+                }
+                // Fan triangulation, fine for the convex polygons modellers export
+                let faces = &mut obj.group(&group, &material).faces;
+                for k in 1..corners.len() - 1 {
+                    faces.push([corners[0], corners[k], corners[k + 1]]);
+                }
+            }
+            "g" | "o" => group = args.join(" "),
+            "usemtl" => material = args.join(" "),
+            "mtllib" => obj.libraries.extend(args.iter().map(|s| s.to_string())),
+            _ => {}
+        }
+    }
+    Ok(obj)
+}
+
+#[derive(Clone)]
+struct MtlMaterial {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    ns: f32,
+    ni: f32,
+    dissolve: f32,
+    illum: u32,
+    map_kd: Option<String>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::zero(),
+            ke: Color::zero(),
+            ns: 0.0,
+            ni: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+}
+
+fn max_component(c: &Color) -> f32 {
+    c.x.max(c.y).max(c.z)
+}
+
+impl MtlMaterial {
+    fn to_material(&self, dir: &Path) -> Arc<dyn Material> {
+        if max_component(&self.ke) > 0.0 {
+            return Arc::new(DiffuseLight::new(SolidColor::new_with_color(self.ke)));
+        }
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let ior = if self.ni > 1.0 { self.ni } else { 1.5 };
+            return Arc::new(Dielectric::new(ior));
+        }
+        if max_component(&self.ks) > max_component(&self.kd) {
+            // Blinn-Phong exponent to a roughness in [0, 1]
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt().min(1.0);
+            return Arc::new(Metal::new(self.ks, fuzz));
+        }
+        if let Some(map) = &self.map_kd {
+            let path = dir.join(map);
+            if path.is_file() {
+                return Arc::new(Lambertian::new(ImageTexture::new(&path.to_string_lossy())));
+            }
+            eprintln!("{}: texture not found, using Kd", path.display());
+        }
+        Arc::new(Lambertian::new(SolidColor::new_with_color(self.kd)))
+    }
+}
+
+fn parse_mtl(file: &str, text: &str) -> Result<HashMap<String, MtlMaterial>, LoadError> {
+    let mut materials = HashMap::new();
+    let mut name: Option<String> = None;
+    let mut current = MtlMaterial::default();
+
+    for (n, line) in text.lines().enumerate() {
+        let n = n + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            if let Some(name) = name.take() {
+                materials.insert(name, current);
+            }
+            name = Some(args.join(" "));
+            current = MtlMaterial::default();
+            continue;
+        }
+
+        let color = |args: &[&str]| -> Result<Color, LoadError> {
+            let c = floats(file, n, args, 1)?;
+            if args.len() >= 3 {
+                let c = floats(file, n, args, 3)?;
+                Ok(Color::new(c[0], c[1], c[2]))
+            } else {
+                Ok(Color::new(c[0], c[0], c[0]))
+            }
+        };
+        match keyword {
+            "Kd" => current.kd = color(&args)?,
+            "Ks" => current.ks = color(&args)?,
+            "Ke" => current.ke = color(&args)?,
+            "Ns" => current.ns = floats(file, n, &args, 1)?[0],
+            "Ni" => current.ni = floats(file, n, &args, 1)?[0],
+            "d" => current.dissolve = floats(file, n, &args, 1)?[0],
+            "Tr" => current.dissolve = 1.0 - floats(file, n, &args, 1)?[0],
+            "illum" => current.illum = floats(file, n, &args, 1)?[0] as u32,
+            // Options such as -s or -bm come first, the file name is last
+            "map_Kd" => current.map_kd = args.last().map(|s| s.to_string()),
+            _ => {}
+        }
+    }
+    if let Some(name) = name {
+        materials.insert(name, current);
+    }
+    Ok(materials)
+}
@@ -0,0 +1,19 @@
+use serde_json::Value;
+
+use super::document::build_scene;
+use super::error::LoadError;
+use super::{read_file, SceneDescription};
+
+pub fn to_value(text: &str) -> Result<Value, LoadError> {
+    serde_yaml::from_str(text).map_err(|e| LoadError::Syntax {
+        message: e.to_string(),
+    })
+}
+
+pub fn from_str(text: &str) -> Result<SceneDescription, LoadError> {
+    build_scene(&to_value(text)?)
+}
+
+pub fn load(file: &str) -> Result<SceneDescription, LoadError> {
+    from_str(&read_file(file)?)
+}
@@ -15,199 +15,198 @@
 
 pub mod base;
 pub mod bvh;
+mod cli;
+pub mod framebuffer;
 pub mod hit;
+pub mod light;
+pub mod loader;
 pub mod objects;
 pub mod pdf;
+mod render;
 mod scene;
 
-use std::sync::mpsc;
 use std::sync::Arc;
-use std::thread;
 
-use image::GenericImage;
-use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
 use threadpool::ThreadPool;
 
+use crate::base::color::{DisplayTransform, ToneMap, Transfer};
 use crate::base::*;
-use crate::hit::hittable_list::HittableList;
-use crate::hit::*;
-use crate::objects::arrect::XZRect;
-use crate::objects::material::Empty;
-use crate::objects::material::Lambertian;
-use crate::objects::material::Material;
-use crate::objects::sphere::Sphere;
-use crate::objects::texture::SolidColor;
+use crate::bvh::bvh::Bvh;
+use crate::cli::Options;
+use crate::framebuffer::checkpoint::{self, RenderSettings};
+use crate::framebuffer::{Accumulator, Framebuffer};
+use crate::light::Lights;
+use crate::loader::{load_scene, save_scene, SceneDescription};
+use crate::render::{render_pass, tiles, Adaptive, Renderer, SampleSchedule};
 use crate::scene::*;
 
-const IMAGE_WIDTH: u32 = 40;
 const ASPECT_RATIO: f32 = 1.0;
-const SAMPLES_PER_PIXEL: u16 = 100;
-const MAX_DEPTH: u16 = 10;
 
 fn main() {
-    const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f32 / ASPECT_RATIO) as u32;
-
-    let world;
-
-    let mut background = Color::zero();
-
-    let lookfrom;
-    let lookat;
-    let mut vfov = 40.0;
-    let vup = Color::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let mut aperture = 0.0;
-    match 0 {
-        1 => {
-            world = random_scene();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::zero();
-            vfov = 20.0;
-            aperture = 0.1;
-        }
-        2 => {
-            world = two_checker_spheres();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::zero();
-            vfov = 20.0;
-        }
-        3 => {
-            world = two_perlin_spheres();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::zero();
-            vfov = 20.0;
-        }
-        4 => {
-            world = earth();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::zero();
-            vfov = 20.0;
-        }
-        5 => {
-            world = simple_light();
-            background = Color::zero();
-            lookfrom = Point3::new(26.0, 3.0, 6.0);
-            lookat = Point3::new(0.0, 2.0, 0.0);
-            vfov = 20.0;
+    let mut options = Options::parse();
+
+    if options.list_scenes {
+        for preset in scene_presets() {
+            println!("{}", preset.name);
         }
-        6 => {
-            world = cornell_box();
-            background = Color::zero();
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        return;
+    }
+
+    // A checkpoint fixes everything that affects the radiance already summed up
+    let mut resumed = None;
+    if let Some(file) = &options.resume {
+        match checkpoint::load(file) {
+            Ok((settings, acc)) => {
+                options.scene = settings.scene;
+                options.width = Some(settings.width);
+                options.height = Some(settings.height);
+                options.max_depth = settings.max_depth;
+                options.seed = settings.seed;
+                resumed = Some(acc);
+            }
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                std::process::exit(1);
+            }
         }
-        7 => {
-            world = cornell_smoke();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+    }
+
+    // Scene construction draws random numbers too (random_scene, Perlin)
+    seed_rng(options.seed);
+
+    let samples_per_pixel = options.samples_per_pixel;
+    let max_depth = options.max_depth;
+
+    let scene: SceneDescription = if options.is_scene_file() {
+        match load_scene(&options.scene) {
+            Ok(scene) => scene,
+            Err(err) => {
+                eprintln!("{}: {}", options.scene, err);
+                std::process::exit(1);
+            }
         }
-        8 => {
-            world = final_scene();
-            lookfrom = Point3::new(478.0, 278.0, -600.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0
+    } else {
+        let aspect_ratio = match (options.width, options.height) {
+            (Some(width), Some(height)) => width as f32 / height as f32,
+            _ => ASPECT_RATIO,
+        };
+        match find_scene(&options.scene) {
+            Some(preset) => preset.describe(aspect_ratio),
+            None => {
+                eprintln!(
+                    "unknown scene `{}`, use --list-scenes to see the built-in scenes",
+                    options.scene
+                );
+                std::process::exit(1);
+            }
         }
-        _ => {
-            world = pic();
-            background = Color::new(0.90, 0.90, 0.97);
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+    };
+    if let Some(file) = &options.export {
+        if let Err(err) = save_scene(&scene, file) {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
+        return;
     }
 
-    //cornell_box
-    let mut lights = HittableList::new();
-    lights.add(Arc::new(XZRect::new(
-        213.0,
-        343.0,
-        227.0,
-        332.0,
-        554.0,
-        Lambertian::new(SolidColor::new_with_color(Color::zero())),
-    )));
-    // lights.add(Arc::new(Sphere::new(
-    //     &Point3::new(190.0, 90.0, 190.0),
-    //     90.0,
-    //     Lambertian::new(SolidColor::new_with_color(Color::zero())),
-    // )));
-
-    let cam = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        vfov,
-        ASPECT_RATIO,
-        aperture,
-        dist_to_focus,
-        0.0,
-        1.0,
+    let (image_width, image_height) = options.resolution(scene.aspect_ratio);
+    // Both sides given with another shape than the scene's: widen or narrow the
+    // view rather than stretch the image
+    let cam = if (image_width as f32 / scene.aspect_ratio) as u32 == image_height {
+        scene.camera
+    } else {
+        scene
+            .camera
+            .with_aspect_ratio(image_width as f32 / image_height as f32)
+    };
+    let lights = Lights::new(scene.world.lights(), scene.environment, scene.lights);
+    let world = Bvh::new(scene.world.objects, cam.time0, cam.time1);
+    println!("{}", world.stats());
+    println!(
+        "Lights: {}",
+        lights.objects.objects.len() + lights.punctual.len()
     );
 
+    let renderer = Arc::new(Renderer {
+        world: Arc::new(world),
+        lights: Arc::new(lights),
+        camera: cam,
+        width: image_width,
+        height: image_height,
+        max_depth,
+        seed: options.seed,
+    });
+
     //多线程
-    let (tx, rx) = mpsc::channel();
-    let num_threads: usize = 8;
-    let num_jobs: u32 = 32;
-    let thread_pool = ThreadPool::new(num_threads);
-
-    let mut img: RgbImage = ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
-    let bar = ProgressBar::new(num_jobs as u64);
-
-
-    for i in 0..num_jobs {
-        let tx_ = tx.clone();
-        let world_ptr = world.clone();
-        let lights_ptr = lights.clone();
-        let cam_ptr = cam.clone(); //when Camera doesn't implement Copy trait
-        let start_height = IMAGE_HEIGHT * i / num_jobs;
-        let finish_height = IMAGE_HEIGHT * (i + 1) / num_jobs;
-
-        thread_pool.execute(move || {
-            let delta_height = finish_height - start_height;
-            let mut _img: RgbImage = ImageBuffer::new(IMAGE_WIDTH, delta_height);
-            for x in 0..IMAGE_WIDTH {
-                for (img_y, y) in (start_height..finish_height).enumerate() {
-                    let mut color = Color::zero();
-                    for s in 0..SAMPLES_PER_PIXEL {
-                        let u = (x as f32 + random_f()) / (IMAGE_WIDTH - 1) as f32;
-                        let v = (IMAGE_HEIGHT as f32 - y as f32 + random_f())
-                            / (IMAGE_HEIGHT - 1) as f32;
-                        let r = cam_ptr.get_ray(u, v);
-                        color += ray_color(
-                            &r,
-                            &background,
-                            &world_ptr,
-                            &Arc::new(lights_ptr.clone()),
-                            MAX_DEPTH,
-                        );
-                    }
-                    let pixel_color = _img.get_pixel_mut(x, img_y as u32);
-                    write_color(&mut color, SAMPLES_PER_PIXEL, pixel_color);
-                }
+    let thread_pool = ThreadPool::new(options.threads.max(1));
+    let spp = samples_per_pixel as u32;
+    // The variance estimate needs at least two samples
+    let adaptive = options.adaptive_threshold.map(|threshold| Adaptive {
+        threshold,
+        min_spp: options.min_spp.max(2).min(spp),
+    });
+    // Adaptive sampling only decides between passes, so it needs more than one
+    let default_pass_spp = adaptive.map_or(spp, |adaptive| adaptive.min_spp);
+    let schedule = SampleSchedule {
+        spp,
+        pass_spp: options.pass_spp.unwrap_or(default_pass_spp).max(1),
+        adaptive,
+    };
+    let mut acc = resumed.unwrap_or_else(|| Accumulator::new(image_width, image_height));
+    let passes = schedule.passes(&acc);
+    let tiles = Arc::new(tiles(image_width, image_height, options.tile_size));
+    let bar = ProgressBar::new(passes as u64 * tiles.len() as u64);
+
+    let settings = RenderSettings {
+        scene: options.scene.clone(),
+        width: image_width,
+        height: image_height,
+        max_depth,
+        seed: options.seed,
+    };
+    let checkpoint_file = options
+        .checkpoint
+        .as_ref()
+        .or_else(|| options.resume.as_ref());
+
+    // Each pass covers every pixel, so the output can be rewritten after it
+    for pass in 0..passes {
+        if render_pass(&renderer, &thread_pool, &mut acc, &schedule, &tiles, &bar) == 0 {
+            break;
+        }
+        if let Some(file) = checkpoint_file {
+            if let Err(err) = checkpoint::save(file, &settings, &acc) {
+                eprintln!("{}: {}", file, err);
             }
-
-            tx_.send((start_height..finish_height, _img))
-                .expect("FAILED IN SENDING");
-        });
+        }
+        if pass + 1 < passes {
+            save_outputs(&acc.resolve(), &options);
+        }
     }
 
-    for (rows, data) in rx.iter().take(num_jobs as usize) {
-        for (idx, row) in rows.enumerate() {
-            for col in 0..IMAGE_WIDTH {
-                *img.get_pixel_mut(col, row) = *data.get_pixel(col, idx as u32);
-            }
+    bar.finish();
+    save_outputs(&acc.resolve(), &options);
+
+    if let Some(file) = &options.sample_map {
+        // Linear grey with --spp samples at white
+        let display = DisplayTransform {
+            tone_map: ToneMap::Clamp,
+            exposure: -(spp.max(1) as f32).log2(),
+            transfer: Transfer::Gamma(1.0),
+        };
+        if let Err(err) = acc.sample_map().save(file, &display) {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
         }
-        bar.inc(1);
     }
+}
 
-    img.save("output/pic.png").unwrap();
-    bar.finish();
+fn save_outputs(fb: &Framebuffer, options: &Options) {
+    for file in &options.outputs {
+        if let Err(err) = fb.save(file, &options.display) {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
+        }
+    }
 }
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use crate::base::document::{number, vec3_value};
 use crate::base::{
     ray::*,
     rtweekend::{random_f, random_f_m, INF},
@@ -9,6 +10,7 @@ use crate::base::{
 use crate::bvh::aabb::AABB;
 use crate::hit::{hittable::*, hittable_list::HittableList};
 use crate::objects::material::Material;
+use serde_json::{json, Value};
 
 #[derive(Clone)]
 pub struct XYRect<T: Material> {
@@ -64,6 +66,39 @@ impl<T: 'static + Clone + Material + Sync + Send> Hittable for XYRect<T> {
             &Point3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(o, v, time), 0.001, INF) {
+            let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+            return solid_angle_pdf(rec.t, v, &rec.normal, area);
+        }
+        0.0
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        let random_point = Point3::new(
+            random_f_m(self.x0, self.x1),
+            random_f_m(self.y0, self.y1),
+            self.k,
+        );
+        random_point - *o
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "XYRect",
+            "x0": number(self.x0),
+            "x1": number(self.x1),
+            "y0": number(self.y0),
+            "y1": number(self.y1),
+            "k": number(self.k),
+            "material": self.mp.to_document()?,
+        }))
+    }
 }
 
 #[derive(Clone)]
@@ -121,18 +156,15 @@ impl<T: 'static + Clone + Material + Sync + Send> Hittable for XZRect<T> {
         ))
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f32 {
-        if let Some(rec) = self.hit(&Ray::new(o, v, 0.0), 0.001, INF) {
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(o, v, time), 0.001, INF) {
             let area = (self.x1 - self.x0) * (self.z1 - self.z0);
-            let squared_distance = rec.t * rec.t * v.squared_length();
-            let cosine = (Vec3::dot(v.clone(), rec.normal) / v.length()).abs();
-
-            return squared_distance / (cosine * area);
+            return solid_angle_pdf(rec.t, v, &rec.normal, area);
         }
         0.0
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
         let random_point = Point3::new(
             random_f_m(self.x0, self.x1),
             self.k,
@@ -140,6 +172,22 @@ impl<T: 'static + Clone + Material + Sync + Send> Hittable for XZRect<T> {
         );
         random_point - o.clone()
     }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "XZRect",
+            "x0": number(self.x0),
+            "x1": number(self.x1),
+            "z0": number(self.z0),
+            "z1": number(self.z1),
+            "k": number(self.k),
+            "material": self.mp.to_document()?,
+        }))
+    }
 }
 
 #[derive(Clone)]
@@ -196,6 +244,39 @@ impl<T: 'static + Clone + Material + Sync + Send> Hittable for YZRect<T> {
             &Point3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(o, v, time), 0.001, INF) {
+            let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+            return solid_angle_pdf(rec.t, v, &rec.normal, area);
+        }
+        0.0
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        let random_point = Point3::new(
+            self.k,
+            random_f_m(self.y0, self.y1),
+            random_f_m(self.z0, self.z1),
+        );
+        random_point - *o
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "YZRect",
+            "y0": number(self.y0),
+            "y1": number(self.y1),
+            "z0": number(self.z0),
+            "z1": number(self.z1),
+            "k": number(self.k),
+            "material": self.mp.to_document()?,
+        }))
+    }
 }
 
 #[derive(Clone)]
@@ -203,6 +284,7 @@ pub struct Box {
     pub box_min: Point3,
     pub box_max: Point3,
     pub slides: HittableList,
+    pub mat_ptr: Arc<dyn Material>,
 }
 
 impl Box {
@@ -211,6 +293,7 @@ impl Box {
             box_min: p0,
             box_max: p1,
             slides: HittableList::new(),
+            mat_ptr: Arc::new(ptr.clone()),
         };
         box0.slides.add(Arc::new(XYRect::new(
             p0.x,
@@ -273,4 +356,27 @@ impl Hittable for Box {
     fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
         Some(AABB::new(&self.box_min, &self.box_max))
     }
+
+    // A face picked uniformly, then a point on it; a direction through the box
+    // counts every face it crosses
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.slides.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.slides.random(o, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Box",
+            "p0": vec3_value(&self.box_min),
+            "p1": vec3_value(&self.box_max),
+            "material": self.mat_ptr.to_document()?,
+        }))
+    }
 }
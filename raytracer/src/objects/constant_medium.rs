@@ -1,13 +1,16 @@
 use super::{material::*, texture::*};
+use crate::base::document::number;
 use crate::base::{ray::Ray, rtweekend::*, vec3::*};
 use crate::bvh::aabb::AABB;
 use crate::hit::hittable::*;
+use serde_json::{json, Value};
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct ConstantMedium<T: Hittable, U: Material> {
     pub boundary: T,
     pub phase_function: U,
+    pub density: f32,
     pub neg_inv_density: f32,
 }
 
@@ -15,6 +18,7 @@ impl<T: Hittable, U: Material> ConstantMedium<T, U> {
     pub fn new(boundary: T, d: f32, a: U) -> Self {
         Self {
             boundary,
+            density: d,
             neg_inv_density: -1.0 / d,
             phase_function: a,
         }
@@ -72,4 +76,26 @@ impl<T: Clone + Hittable, U: 'static + Clone + Material + Sync + Send> Hittable
         }
         None
     }
+
+    // Directions towards the boundary, which encloses every point that can scatter
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        self.boundary.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        self.boundary.random(o, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.phase_function.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "ConstantMedium",
+            "density": number(self.density),
+            "boundary": self.boundary.to_document()?,
+            "phase_function": self.phase_function.to_document()?,
+        }))
+    }
 }
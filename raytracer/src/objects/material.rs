@@ -1,18 +1,22 @@
+use super::microfacet::*;
 use super::texture::*;
+use crate::base::color::blackbody;
+use crate::base::document::{number, vec3_value};
 use crate::base::onb::Onb;
 use crate::base::rtweekend::{random_cosine_direction, PI};
 use crate::base::{ray::*, rtweekend::random_f, vec3::*};
 use crate::hit::hittable::*;
 use crate::pdf::cosine_pdf::CosinePdf;
+use crate::pdf::sphere_pdf::SpherePdf;
 use crate::pdf::Pdf;
-use std::collections::hash_map::RandomState;
+use serde_json::{json, Value};
 use std::sync::Arc;
 
 pub struct ScatterRecord {
     pub specular_ray: Ray,
     pub is_specular: bool,
     pub attenuation: Color,
-    pub pdf_ptr: CosinePdf,
+    pub pdf_ptr: Box<dyn Pdf>,
 }
 
 impl ScatterRecord {
@@ -20,7 +24,7 @@ impl ScatterRecord {
         ScatterRecord {
             attenuation: Color::zero(),
             specular_ray: Ray::new_default(&Point3::zero(), &Vec3::zero()),
-            pdf_ptr: CosinePdf::new(&Color::ones()),
+            pdf_ptr: Box::new(CosinePdf::new(&Color::ones())),
             is_specular: false,
         }
     }
@@ -33,10 +37,53 @@ pub trait Material: Sync + Send {
         0.0
     }
 
+    // BSDF times the cosine towards `scattered`, which ray_color scales by the
+    // record's attenuation. Materials colored only by their albedo can keep the
+    // default and return it as the attenuation
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        Color::ones() * self.scattering_pdf(r_in, rec, scattered)
+    }
+
     fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Point3) -> Color {
         Color::zero()
     }
+
+    // Whether `emitted` can be non-zero, which makes the objects using it lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        None
+    }
+}
+
+impl Material for Arc<dyn Material> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.as_ref().scatter(r_in, rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        self.as_ref().scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.as_ref().eval(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Point3) -> Color {
+        self.as_ref().emitted(r_in, rec, u, v, p)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.as_ref().is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        self.as_ref().to_document()
+    }
 }
+
 pub struct Empty {}
 
 impl Empty {
@@ -49,6 +96,10 @@ impl Material for Empty {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         None
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({ "type": "Empty" }))
+    }
 }
 
 #[derive(Clone)]
@@ -68,7 +119,7 @@ impl<T: Texture> Material for Lambertian<T> {
         Some(ScatterRecord {
             attenuation,
             specular_ray: Ray::new_default(&Point3::zero(), &Vec3::zero()),
-            pdf_ptr: CosinePdf::new(&rec.normal),
+            pdf_ptr: Box::new(CosinePdf::new(&rec.normal)),
             is_specular: false,
         })
     }
@@ -80,6 +131,13 @@ impl<T: Texture> Material for Lambertian<T> {
         }
         cosine / PI
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Lambertian",
+            "albedo": self.albedo.to_document()?,
+        }))
+    }
 }
 
 #[derive(Clone)]
@@ -110,10 +168,18 @@ impl Material for Metal {
         Some(ScatterRecord {
             attenuation,
             specular_ray: scattered,
-            pdf_ptr: CosinePdf::new(&Color::ones()),
+            pdf_ptr: Box::new(CosinePdf::new(&Color::ones())),
             is_specular: true,
         })
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Metal",
+            "albedo": vec3_value(&self.albedo),
+            "fuzz": number(self.fuzz),
+        }))
+    }
 }
 
 #[derive(Clone)]
@@ -171,15 +237,310 @@ impl Material for Dielectric {
         Some(ScatterRecord {
             attenuation,
             specular_ray: scattered,
-            pdf_ptr: CosinePdf::new(&Color::ones()),
+            pdf_ptr: Box::new(CosinePdf::new(&Color::ones())),
             is_specular: true,
         })
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Dielectric",
+            "ref_idx": number(self.ir),
+        }))
+    }
+}
+
+// Shading frame around the normal and the direction back along the incoming ray in it
+fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let uvw = Onb::build_from_w(&rec.normal);
+    let wo = uvw.to_local(&-r_in.dir.unit());
+    (uvw, wo)
+}
+
+// Complex indices of refraction at roughly 650, 550 and 450 nm
+const CONDUCTORS: &[(&str, [f32; 3], [f32; 3])] = &[
+    ("gold", [0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+    ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+    ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("aluminium", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("iron", [2.868, 2.917, 2.654], [3.083, 2.933, 2.815]),
+    ("chromium", [3.181, 3.179, 2.341], [3.329, 3.331, 3.087]),
+    ("titanium", [2.741, 2.541, 2.267], [3.814, 3.435, 3.039]),
+];
+
+// GGX microfacet conductor; `roughness` is perceptual, squared into the GGX alpha
+#[derive(Clone)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    pub roughness: f32,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f32) -> Conductor {
+        Conductor { eta, k, roughness }
+    }
+
+    pub fn preset(name: &str, roughness: f32) -> Option<Conductor> {
+        let name = if name == "aluminum" {
+            "aluminium"
+        } else {
+            name
+        };
+        CONDUCTORS
+            .iter()
+            .find(|(preset, _, _)| *preset == name)
+            .map(|(_, eta, k)| {
+                Conductor::new(
+                    Color::new(eta[0], eta[1], eta[2]),
+                    Color::new(k[0], k[1], k[2]),
+                    roughness,
+                )
+            })
+    }
+
+    pub fn preset_names() -> impl Iterator<Item = &'static str> {
+        CONDUCTORS.iter().map(|(name, _, _)| *name)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord) -> ConductorPdf {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        ConductorPdf {
+            uvw,
+            wo,
+            ggx: Ggx::from_roughness(self.roughness, 0.0),
+        }
+    }
+}
+
+// Reflection about GGX visible normals
+struct ConductorPdf {
+    uvw: Onb,
+    wo: Vec3,
+    ggx: Ggx,
+}
+
+impl Pdf for ConductorPdf {
+    fn value(&self, direction: &Vec3) -> f32 {
+        let wi = self.uvw.to_local(&direction.unit());
+        if wi.z <= 0.0 || self.wo.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (self.wo + wi).unit();
+        self.ggx.pdf_visible(&self.wo, &h) / (4.0 * Vec3::dot(self.wo, h))
+    }
+
+    fn generate(&self) -> Vec3 {
+        let h = self.ggx.sample_visible(&self.wo, random_f(), random_f());
+        let wi = reflect_about(&self.wo, &h);
+        if wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+        self.uvw.local(&wi)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let pdf = self.pdf(r_in, rec);
+        if pdf.wo.z <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: Color::ones(),
+            specular_ray: Ray::new_default(&Point3::zero(), &Vec3::zero()),
+            pdf_ptr: Box::new(pdf),
+            is_specular: false,
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        self.pdf(r_in, rec).value(&scattered.dir)
+    }
+
+    // F * D * G / (4 cos_o), the cosine at the light side cancelling
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let pdf = self.pdf(r_in, rec);
+        let (wo, ggx) = (pdf.wo, pdf.ggx);
+        let wi = pdf.uvw.to_local(&scattered.dir.unit());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::zero();
+        }
+        let h = (wo + wi).unit();
+        fresnel_conductor(Vec3::dot(wo, h), &self.eta, &self.k)
+            * (ggx.d(&h) * ggx.g(&wo, &wi) / (4.0 * wo.z))
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Conductor",
+            "eta": vec3_value(&self.eta),
+            "k": vec3_value(&self.k),
+            "roughness": number(self.roughness),
+        }))
+    }
+}
+
+// Rough glass: GGX microfacets that reflect or refract by their Fresnel term. Like
+// Dielectric, radiance is not rescaled by eta squared across the boundary
+#[derive(Clone)]
+pub struct RoughDielectric {
+    pub ir: f32,
+    pub roughness: f32,
+}
+
+impl RoughDielectric {
+    pub fn new(index_of_refraction: f32, roughness: f32) -> RoughDielectric {
+        RoughDielectric {
+            ir: index_of_refraction,
+            roughness,
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord) -> RoughDielectricPdf {
+        let (uvw, wo) = shading_frame(r_in, rec);
+        RoughDielectricPdf {
+            uvw,
+            wo,
+            ggx: Ggx::from_roughness(self.roughness, 0.0),
+            eta: if rec.front_face {
+                self.ir
+            } else {
+                1.0 / self.ir
+            },
+        }
+    }
+}
+
+// Visible normals, then reflection or refraction chosen by the Fresnel term
+struct RoughDielectricPdf {
+    uvw: Onb,
+    wo: Vec3,
+    ggx: Ggx,
+    eta: f32,
+}
+
+impl RoughDielectricPdf {
+    // Half vector of a refraction from `wo` to `wi`, on the side of `wo`
+    fn refraction_half(&self, wi: &Vec3) -> Option<Vec3> {
+        let h = self.wo + self.eta * *wi;
+        if h.near_zero() {
+            return None;
+        }
+        let h = if h.z < 0.0 { -h.unit() } else { h.unit() };
+        if Vec3::dot(self.wo, h) <= 0.0 || Vec3::dot(*wi, h) >= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+}
+
+impl Pdf for RoughDielectricPdf {
+    fn value(&self, direction: &Vec3) -> f32 {
+        let (wo, ggx, eta) = (self.wo, self.ggx, self.eta);
+        let wi = self.uvw.to_local(&direction.unit());
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+        if wi.z > 0.0 {
+            let h = (wo + wi).unit();
+            let fresnel = fresnel_dielectric(Vec3::dot(wo, h), eta);
+            return fresnel * ggx.pdf_visible(&wo, &h) / (4.0 * Vec3::dot(wo, h));
+        }
+        let h = match self.refraction_half(&wi) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let (o, i) = (Vec3::dot(wo, h), Vec3::dot(wi, h));
+        let denom = o + eta * i;
+        (1.0 - fresnel_dielectric(o, eta)) * ggx.pdf_visible(&wo, &h) * eta * eta * -i
+            / (denom * denom)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let h = self.ggx.sample_visible(&self.wo, random_f(), random_f());
+        let reflect = random_f() < fresnel_dielectric(Vec3::dot(self.wo, h), self.eta);
+        let wi = if reflect {
+            reflect_about(&self.wo, &h)
+        } else {
+            match refract_about(&self.wo, &h, self.eta) {
+                Some(wi) => wi,
+                None => return Vec3::zero(),
+            }
+        };
+        if reflect != (wi.z > 0.0) {
+            return Vec3::zero();
+        }
+        self.uvw.local(&wi)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let pdf = self.pdf(r_in, rec);
+        if pdf.wo.z <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: Color::ones(),
+            specular_ray: Ray::new_default(&Point3::zero(), &Vec3::zero()),
+            pdf_ptr: Box::new(pdf),
+            is_specular: false,
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        self.pdf(r_in, rec).value(&scattered.dir)
+    }
+
+    // Sampling by F leaves G / G1(wo) as the weight on either branch
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let pdf = self.pdf(r_in, rec);
+        let (wo, ggx, eta) = (pdf.wo, pdf.ggx, pdf.eta);
+        let wi = pdf.uvw.to_local(&scattered.dir.unit());
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return Color::zero();
+        }
+        if wi.z > 0.0 {
+            let h = (wo + wi).unit();
+            let fresnel = fresnel_dielectric(Vec3::dot(wo, h), eta);
+            return Color::ones() * (fresnel * ggx.d(&h) * ggx.g(&wo, &wi) / (4.0 * wo.z));
+        }
+        let h = match pdf.refraction_half(&wi) {
+            Some(h) => h,
+            None => return Color::zero(),
+        };
+        let (o, i) = (Vec3::dot(wo, h), Vec3::dot(wi, h));
+        let denom = o + eta * i;
+        let f =
+            (1.0 - fresnel_dielectric(o, eta)) * ggx.d(&h) * ggx.g(&wo, &wi) * eta * eta * o * -i
+                / (wo.z * denom * denom);
+        Color::ones() * f
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "RoughDielectric",
+            "ref_idx": number(self.ir),
+            "roughness": number(self.roughness),
+        }))
+    }
 }
 
+// Emits `emit` times `intensity` from the front face, or from both with
+// `two_sided`. A colour temperature tints the emission, and a profile texture
+// looked up at (0.5, angle from the normal / 90 degrees) shapes it like an IES
+// candela distribution. A power is kept as given and shared between the sides
+// when emitting, so the builders can run in any order
 #[derive(Clone)]
 pub struct DiffuseLight<T: Texture> {
     pub emit: T,
+    intensity: f32,
+    power: Option<(f32, f32)>,
+    two_sided: bool,
+    temperature: Option<f32>,
+    profile: Option<Arc<dyn Texture>>,
+    tint: Color,
 }
 
 impl<T: Texture> Material for DiffuseLight<T> {
@@ -188,16 +549,102 @@ impl<T: Texture> Material for DiffuseLight<T> {
     }
 
     fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Point3) -> Color {
-        if rec.front_face {
-            return self.emit.value(u, v, p);
+        if !rec.front_face && !self.two_sided {
+            return Color::zero();
         }
-        Color::zero()
+        let mut radiance = Vec3::elemul(self.emit.value(u, v, p), self.tint) * self.intensity();
+        if let Some(profile) = &self.profile {
+            // The normal faces the incoming ray, whichever side it hit
+            let cos_theta = Vec3::dot(-r_in.dir.unit(), rec.normal).min(1.0).max(0.0);
+            let angle = cos_theta.acos() / (0.5 * PI);
+            radiance = Vec3::elemul(radiance, profile.value(0.5, angle, p));
+        }
+        radiance
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut doc = json!({
+            "type": "DiffuseLight",
+            "emit": self.emit.to_document()?,
+        });
+        if self.intensity() != 1.0 {
+            doc["intensity"] = number(self.intensity());
+        }
+        if self.two_sided {
+            doc["two_sided"] = Value::Bool(true);
+        }
+        if let Some(temperature) = self.temperature {
+            doc["temperature"] = number(temperature);
+        }
+        if let Some(profile) = &self.profile {
+            doc["profile"] = profile.to_document()?;
+        }
+        Some(doc)
     }
 }
 
+// Luminous efficacy of 555 nm light, in lumens per watt
+const LUMENS_PER_WATT: f32 = 683.0;
+
 impl<T: Texture> DiffuseLight<T> {
     pub fn new(a: T) -> DiffuseLight<T> {
-        DiffuseLight { emit: a }
+        DiffuseLight {
+            emit: a,
+            intensity: 1.0,
+            power: None,
+            two_sided: false,
+            temperature: None,
+            profile: None,
+            tint: Color::ones(),
+        }
+    }
+
+    pub fn two_sided(mut self) -> DiffuseLight<T> {
+        self.two_sided = true;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> DiffuseLight<T> {
+        self.intensity = intensity;
+        self.power = None;
+        self
+    }
+
+    // Sets the intensity so a surface of `area` gives off `watts` for an `emit`
+    // of one in each channel; a profile is not accounted for
+    pub fn with_power(mut self, watts: f32, area: f32) -> DiffuseLight<T> {
+        self.power = Some((watts, area));
+        self
+    }
+
+    pub fn with_lumens(self, lumens: f32, area: f32) -> DiffuseLight<T> {
+        self.with_power(lumens / LUMENS_PER_WATT, area)
+    }
+
+    // Tints the emission with the colour of a black body at `kelvin`
+    pub fn with_temperature(mut self, kelvin: f32) -> DiffuseLight<T> {
+        self.temperature = Some(kelvin);
+        self.tint = blackbody(kelvin);
+        self
+    }
+
+    pub fn with_profile(mut self, profile: Arc<dyn Texture>) -> DiffuseLight<T> {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn intensity(&self) -> f32 {
+        match self.power {
+            Some((watts, area)) => {
+                let sides = if self.two_sided { 2.0 } else { 1.0 };
+                watts / (PI * area * sides)
+            }
+            None => self.intensity,
+        }
     }
 }
 
@@ -214,13 +661,153 @@ impl<T: Texture> Isotropic<T> {
 
 impl<T: Texture> Material for Isotropic<T> {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let scattered = Ray::new(&rec.p, &random_in_unit_sphere(), r_in.tm);
         let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
         Some(ScatterRecord {
             attenuation,
-            specular_ray: scattered,
-            pdf_ptr: CosinePdf::new(&Color::ones()),
-            is_specular: true,
+            specular_ray: Ray::new_default(&Point3::zero(), &Vec3::zero()),
+            pdf_ptr: Box::new(SpherePdf::new()),
+            is_specular: false,
         })
     }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Isotropic",
+            "albedo": self.albedo.to_document()?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::rtweekend::seed_rng;
+
+    #[test]
+    fn test_microfacet_pdfs() {
+        seed_rng(9);
+        let r_in = Ray::new(
+            &Point3::new(0.0, 1.0, 1.0),
+            &Vec3::new(0.2, -0.6, -1.0),
+            0.0,
+        );
+        let materials: Vec<Arc<dyn Material>> = vec![
+            Arc::new(Conductor::preset("gold", 0.5).unwrap()),
+            Arc::new(RoughDielectric::new(1.5, 0.4)),
+        ];
+        for front_face in &[true, false] {
+            for mat_ptr in &materials {
+                let rec = HitRecord {
+                    p: Point3::zero(),
+                    normal: Vec3::new(0.0, 0.0, 1.0),
+                    mat_ptr: mat_ptr.clone(),
+                    t: 1.0,
+                    u: 0.0,
+                    v: 0.0,
+                    front_face: *front_face,
+                };
+                // Scattering goes through the record's pdf, so light samples can mix in
+                let srec = mat_ptr.scatter(&r_in, &rec).unwrap();
+                assert!(!srec.is_specular);
+                for _ in 0..100 {
+                    let direction = random_in_unit_vector();
+                    let scattered = Ray::new(&rec.p, &direction, 0.0);
+                    let pdf = mat_ptr.scattering_pdf(&r_in, &rec, &scattered);
+                    assert_eq!(srec.pdf_ptr.value(&direction), pdf);
+                }
+
+                // The pdf integrates to the fraction of samples the record's pdf keeps
+                let n = 200000;
+                let mut kept = 0.0;
+                let mut integral = 0.0;
+                for _ in 0..n {
+                    let direction = mat_ptr.scatter(&r_in, &rec).unwrap().pdf_ptr.generate();
+                    if !direction.near_zero() {
+                        kept += 1.0 / n as f32;
+                        // Sample weights are G / G1, never above one
+                        let scattered = Ray::new(&rec.p, &direction, 0.0);
+                        let weight = mat_ptr.eval(&r_in, &rec, &scattered).x
+                            / mat_ptr.scattering_pdf(&r_in, &rec, &scattered);
+                        assert!(weight <= 1.0 + 1e-3, "{}", weight);
+                    }
+                    let scattered = Ray::new(&rec.p, &random_in_unit_vector(), 0.0);
+                    integral +=
+                        mat_ptr.scattering_pdf(&r_in, &rec, &scattered) * 4.0 * PI / n as f32;
+                }
+                // Leaving the glass the refracted lobe piles up near the critical
+                // angle, which uniform directions resolve poorly
+                assert!((integral - kept).abs() < 0.05, "{} {}", integral, kept);
+            }
+        }
+    }
+
+    // Dims linearly from the normal to grazing
+    struct Falloff;
+
+    impl Texture for Falloff {
+        fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
+            Color::ones() * (1.0 - v)
+        }
+    }
+
+    #[test]
+    fn test_diffuse_light_options() {
+        let white = SolidColor::new(1.0, 1.0, 1.0);
+        let rec = |front_face: bool| HitRecord {
+            p: Point3::zero(),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            mat_ptr: Arc::new(Empty::new()),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face,
+        };
+        let straight = Ray::new(&Point3::new(0.0, 1.0, 0.0), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let emitted = |light: &dyn Material, ray: &Ray, front_face: bool| {
+            light.emitted(ray, &rec(front_face), 0.0, 0.0, &Point3::zero())
+        };
+
+        let one_sided = DiffuseLight::new(white.clone()).with_intensity(3.0);
+        assert_eq!(
+            emitted(&one_sided, &straight, true),
+            Color::new(3.0, 3.0, 3.0)
+        );
+        assert_eq!(emitted(&one_sided, &straight, false), Color::zero());
+        let two_sided = one_sided.clone().two_sided();
+        assert_eq!(
+            emitted(&two_sided, &straight, false),
+            Color::new(3.0, 3.0, 3.0)
+        );
+
+        // A 2 m^2 panel giving off 2 pi W from both sides has radiance 1/2,
+        // whichever order the builders run in
+        let panel = DiffuseLight::new(white.clone())
+            .two_sided()
+            .with_power(2.0 * PI, 2.0);
+        assert!((panel.intensity() - 0.5).abs() < 1e-6);
+        let panel = DiffuseLight::new(white.clone())
+            .with_power(2.0 * PI, 2.0)
+            .two_sided();
+        assert!((panel.intensity() - 0.5).abs() < 1e-6);
+        assert!((emitted(&panel, &straight, false).x - 0.5).abs() < 1e-6);
+        let bulb = DiffuseLight::new(white.clone()).with_lumens(683.0 * PI, 1.0);
+        assert!((bulb.intensity() - 1.0).abs() < 1e-6);
+
+        let warm = DiffuseLight::new(white.clone()).with_temperature(2700.0);
+        let color = emitted(&warm, &straight, true);
+        assert!(color.x > color.z);
+
+        let shaped = DiffuseLight::new(white).with_profile(Arc::new(Falloff));
+        let grazing = Ray::new(
+            &Point3::new(-1.0, 1.0, 0.0),
+            &Vec3::new(1.0, -1.0, 0.0),
+            0.0,
+        );
+        assert_eq!(emitted(&shaped, &straight, true), Color::ones());
+        assert!((emitted(&shaped, &grazing, true).x - 0.5).abs() < 1e-5);
+    }
 }
@@ -0,0 +1,183 @@
+use crate::base::rtweekend::PI;
+use crate::base::vec3::{Color, Vec3};
+
+// Trowbridge-Reitz (GGX) normal distribution in a local frame with the surface
+// normal along +z and the anisotropy axes along x and y
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Ggx {
+        Ggx {
+            alpha_x: alpha_x.max(1e-3),
+            alpha_y: alpha_y.max(1e-3),
+        }
+    }
+
+    // Perceptual roughness squared, stretched along x by `anisotropic` in [0, 1]
+    pub fn from_roughness(roughness: f32, anisotropic: f32) -> Ggx {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropic).sqrt();
+        Ggx::new(alpha / aspect, alpha * aspect)
+    }
+
+    pub fn d(&self, h: &Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x / self.alpha_x;
+        let y = h.y / self.alpha_y;
+        let t = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    pub fn lambda(&self, w: &Vec3) -> f32 {
+        let z2 = w.z * w.z;
+        if z2 == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2_tan2 = (self.alpha_x * self.alpha_x * w.x * w.x
+            + self.alpha_y * self.alpha_y * w.y * w.y)
+            / z2;
+        0.5 * ((1.0 + a2_tan2).sqrt() - 1.0)
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height-correlated masking-shadowing
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Heitz 2018, "Sampling the GGX Distribution of Visible Normals"; `wo` must be
+    // above the surface
+    pub fn sample_visible(&self, wo: &Vec3, u1: f32, u2: f32) -> Vec3 {
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(vh, t1);
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit()
+    }
+
+    // Density of `sample_visible` over half vectors
+    pub fn pdf_visible(&self, wo: &Vec3, h: &Vec3) -> f32 {
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vec3::dot(*wo, *h).max(0.0) * self.d(h) / wo.z.abs()
+    }
+}
+
+pub fn schlick_weight(cosine: f32) -> f32 {
+    let m = (1.0 - cosine).max(0.0).min(1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+pub fn schlick(f0: Color, cosine: f32) -> Color {
+    f0 + (Color::ones() - f0) * schlick_weight(cosine)
+}
+
+// Unpolarized Fresnel reflectance of a dielectric boundary; `eta` is the index
+// on the far side over the index on the near side
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.abs().min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// Reflectance of a conductor with complex index eta + ik per channel, seen from air
+pub fn fresnel_conductor(cos_i: f32, eta: &Color, k: &Color) -> Color {
+    let cos2 = cos_i.abs().min(1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f32, k: f32| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos2.sqrt() * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Color::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+// Mirror `wo` about the microfacet normal `h`
+pub fn reflect_about(wo: &Vec3, h: &Vec3) -> Vec3 {
+    2.0 * Vec3::dot(*wo, *h) * *h - *wo
+}
+
+// Refract `wo` through the microfacet normal `h`, which lies on the side of
+// `wo`; None on total internal reflection
+pub fn refract_about(wo: &Vec3, h: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = Vec3::dot(*wo, *h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + (cos_i / eta - cos_t) * *h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::rtweekend::{random_f, seed_rng};
+
+    #[test]
+    fn test_visible_normals() {
+        seed_rng(11);
+        let ggx = Ggx::new(0.3, 0.6);
+        let wo = Vec3::new(0.4, -0.2, 0.7).unit();
+
+        // Projected visible area integrates to one: E[1 / pdf] over the samples
+        // equals the integral of D(h) h.z over the hemisphere of half vectors
+        let n = 20000;
+        let mut area = 0.0;
+        for _ in 0..n {
+            let h = ggx.sample_visible(&wo, random_f(), random_f());
+            assert!(h.z > 0.0 && Vec3::dot(wo, h) >= -1e-4);
+            area += ggx.d(&h) * h.z / ggx.pdf_visible(&wo, &h) / n as f32;
+        }
+        assert!((area - 1.0).abs() < 0.03, "{}", area);
+
+        let h = Vec3::new(0.1, 0.2, 0.9).unit();
+        let wi = refract_about(&wo, &h, 1.5).unwrap();
+        assert!((wi.length() - 1.0).abs() < 1e-5 && wi.z < 0.0);
+        assert!(refract_about(&Vec3::new(0.9, 0.0, 0.1).unit(), &h, 1.0 / 1.5).is_none());
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+
+        // With k = 0 a conductor is a dielectric seen from outside
+        let f = fresnel_conductor(0.6, &Color::new(1.5, 1.5, 1.5), &Color::zero());
+        assert!((f.x - fresnel_dielectric(0.6, 1.5)).abs() < 1e-5);
+        assert!(fresnel_conductor(0.01, &Color::new(0.14, 0.37, 1.44), &Color::ones()).x > 0.9);
+    }
+}
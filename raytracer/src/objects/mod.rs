@@ -1,7 +1,10 @@
 pub mod arrect;
 pub mod constant_medium;
 pub mod material;
+pub mod microfacet;
 pub mod moving_sphere;
 pub mod perlin;
+pub mod principled;
 pub mod sphere;
 pub mod texture;
+pub mod triangle;
@@ -1,7 +1,10 @@
 use super::material::Material;
-use crate::base::{ray::Ray, vec3::*};
+use super::sphere::{cone_pdf, random_to_sphere};
+use crate::base::document::{number, vec3_value};
+use crate::base::{onb::Onb, ray::Ray, rtweekend::INF, vec3::*};
 use crate::bvh::aabb::*;
 use crate::hit::hittable::*;
+use serde_json::{json, Value};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -91,4 +94,33 @@ impl<T: 'static + Clone + Material + Sync + Send> Hittable for MovingSphere<T> {
         );
         Some(surrounding_box(&box0, &box1))
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if self.hit(&Ray::new(o, v, time), 0.001, INF).is_none() {
+            return 0.0;
+        }
+        cone_pdf(self.radius, (self.center(time) - *o).squared_length())
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        let direction = self.center(time) - *o;
+        let uvw = Onb::build_from_w(&direction);
+        uvw.local(&random_to_sphere(self.radius, direction.squared_length()))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "MovingSphere",
+            "center0": vec3_value(&self.center0),
+            "center1": vec3_value(&self.center1),
+            "time0": number(self.time0),
+            "time1": number(self.time1),
+            "radius": number(self.radius),
+            "material": self.mat_ptr.to_document()?,
+        }))
+    }
 }
@@ -0,0 +1,454 @@
+use super::material::{Material, ScatterRecord};
+use super::microfacet::*;
+use super::texture::{SolidColor, Texture};
+use crate::base::color::luminance;
+use crate::base::document::number;
+use crate::base::onb::Onb;
+use crate::base::rtweekend::{random_cosine_direction, random_f, PI};
+use crate::base::{ray::Ray, vec3::*};
+use crate::hit::hittable::HitRecord;
+use crate::pdf::Pdf;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+// Disney principled BSDF (Burley 2012, with the 2015 transmission lobe). Every
+// parameter is a texture; the scalar ones read the red channel
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub anisotropic: Arc<dyn Texture>,
+    pub ior: f32,
+}
+
+pub fn constant(x: f32) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(x, x, x))
+}
+
+impl Principled {
+    pub fn new(base_color: Arc<dyn Texture>) -> Principled {
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            anisotropic: constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    // The parameters at a hit point, in the shading frame around its normal
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord) -> PrincipledPdf {
+        let scalar = |t: &Arc<dyn Texture>| t.value(rec.u, rec.v, &rec.p).x.max(0.0).min(1.0);
+        let roughness = scalar(&self.roughness);
+        let lobes = Lobes {
+            base: self.base_color.value(rec.u, rec.v, &rec.p),
+            metallic: scalar(&self.metallic),
+            roughness,
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_gloss: scalar(&self.clearcoat_gloss),
+            transmission: scalar(&self.transmission),
+            ggx: Ggx::from_roughness(roughness, scalar(&self.anisotropic)),
+            eta: if rec.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+        };
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.dir.unit());
+        PrincipledPdf { lobes, uvw, wo }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let pdf = self.pdf(r_in, rec);
+        if pdf.wo.z <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: Color::ones(),
+            specular_ray: Ray::new_default(&Point3::zero(), &Vec3::zero()),
+            pdf_ptr: Box::new(pdf),
+            is_specular: false,
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        self.pdf(r_in, rec).value(&scattered.dir)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let pdf = self.pdf(r_in, rec);
+        let wi = pdf.uvw.to_local(&scattered.dir.unit());
+        pdf.lobes.eval(&pdf.wo, &wi) * wi.z.abs()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Principled",
+            "base_color": self.base_color.to_document()?,
+            "metallic": self.metallic.to_document()?,
+            "roughness": self.roughness.to_document()?,
+            "specular": self.specular.to_document()?,
+            "specular_tint": self.specular_tint.to_document()?,
+            "sheen": self.sheen.to_document()?,
+            "sheen_tint": self.sheen_tint.to_document()?,
+            "clearcoat": self.clearcoat.to_document()?,
+            "clearcoat_gloss": self.clearcoat_gloss.to_document()?,
+            "transmission": self.transmission.to_document()?,
+            "anisotropic": self.anisotropic.to_document()?,
+            "ior": number(self.ior),
+        }))
+    }
+}
+
+// Picks a lobe and samples it; the density covers every lobe that could have
+// produced the direction
+struct PrincipledPdf {
+    lobes: Lobes,
+    uvw: Onb,
+    wo: Vec3,
+}
+
+impl Pdf for PrincipledPdf {
+    fn value(&self, direction: &Vec3) -> f32 {
+        self.lobes
+            .pdf(&self.wo, &self.uvw.to_local(&direction.unit()))
+    }
+
+    fn generate(&self) -> Vec3 {
+        match self.lobes.sample(&self.wo) {
+            Some(wi) => self.uvw.local(&wi),
+            None => Vec3::zero(),
+        }
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f32) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+// Generalized Trowbridge-Reitz with gamma = 1, used by the clearcoat
+fn gtr1(cos_h: f32, alpha: f32) -> f32 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+fn smith_g1(cos: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let c2 = cos * cos;
+    2.0 * cos / (cos + (a2 + c2 - a2 * c2).sqrt())
+}
+
+struct Lobes {
+    base: Color,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    clearcoat: f32,
+    clearcoat_gloss: f32,
+    transmission: f32,
+    ggx: Ggx,
+    eta: f32,
+}
+
+impl Lobes {
+    fn tint(&self) -> Color {
+        let lum = luminance(&self.base);
+        if lum > 0.0 {
+            self.base / lum
+        } else {
+            Color::ones()
+        }
+    }
+
+    fn diffuse_weight(&self) -> f32 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn glass_weight(&self) -> f32 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    fn clearcoat_alpha(&self) -> f32 {
+        0.1 * (1.0 - self.clearcoat_gloss) + 0.001 * self.clearcoat_gloss
+    }
+
+    // Specular reflectance: tinted Schlick for the opaque part, exact dielectric
+    // Fresnel for the part that can also transmit
+    fn specular_fresnel(&self, cos_d: f32) -> Color {
+        let dielectric =
+            self.specular * 0.08 * lerp(Color::ones(), self.tint(), self.specular_tint);
+        let f0 = lerp(dielectric, self.base, self.metallic);
+        let glass = self.glass_weight();
+        schlick(f0, cos_d) * (1.0 - glass)
+            + Color::ones() * (glass * fresnel_dielectric(cos_d, self.eta))
+    }
+
+    // Probabilities of sampling the diffuse, specular, transmission and clearcoat lobes
+    fn lobe_probabilities(&self, wo: &Vec3) -> [f32; 4] {
+        let fresnel = fresnel_dielectric(wo.z, self.eta);
+        let glass = self.glass_weight();
+        let mut p = [
+            self.diffuse_weight(),
+            1.0 - glass + glass * fresnel,
+            glass * (1.0 - fresnel),
+            0.25 * self.clearcoat,
+        ];
+        let total: f32 = p.iter().sum();
+        for x in p.iter_mut() {
+            *x /= total;
+        }
+        p
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if wi.z < 0.0 {
+            return self.eval_transmission(wo, wi);
+        }
+        let h = *wo + *wi;
+        if wi.z == 0.0 || h.near_zero() {
+            return Color::zero();
+        }
+        let h = h.unit();
+        let cos_d = Vec3::dot(*wi, h);
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let sheen = lerp(Color::ones(), self.tint(), self.sheen_tint)
+            * (self.sheen * schlick_weight(cos_d));
+        let mut f = (self.base * (fd / PI) + sheen) * self.diffuse_weight();
+
+        let spec = self.ggx.d(&h) * self.ggx.g(wo, wi) / (4.0 * wo.z * wi.z);
+        f += self.specular_fresnel(cos_d) * spec;
+
+        if self.clearcoat > 0.0 {
+            let fr = 0.04 + 0.96 * schlick_weight(cos_d);
+            let gr = smith_g1(wo.z, 0.25) * smith_g1(wi.z, 0.25);
+            let dr = gtr1(h.z, self.clearcoat_alpha());
+            f += Color::ones() * (0.25 * self.clearcoat * fr * gr * dr / (4.0 * wo.z * wi.z));
+        }
+        f
+    }
+
+    fn transmission_half(&self, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+        let h = *wo + self.eta * *wi;
+        if self.glass_weight() <= 0.0 || h.near_zero() {
+            return None;
+        }
+        let h = h.unit();
+        let h = if h.z < 0.0 { -h } else { h };
+        // Both directions must be on their own side of the microfacet
+        if Vec3::dot(*wo, h) <= 0.0 || Vec3::dot(*wi, h) >= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+
+    fn eval_transmission(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let h = match self.transmission_half(wo, wi) {
+            Some(h) => h,
+            None => return Color::zero(),
+        };
+        let (o, i) = (Vec3::dot(*wo, h), Vec3::dot(*wi, h));
+        let denom = o + self.eta * i;
+        let fresnel = fresnel_dielectric(o, self.eta);
+        let f = (1.0 - fresnel) * self.ggx.d(&h) * self.ggx.g(wo, wi) * (i * o).abs()
+            / ((wi.z * wo.z).abs() * denom * denom);
+        self.base * (self.glass_weight() * f)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let [diffuse, specular, glass, clearcoat] = self.lobe_probabilities(wo);
+        if wi.z < 0.0 {
+            return match self.transmission_half(wo, wi) {
+                Some(h) => {
+                    let (o, i) = (Vec3::dot(*wo, h), Vec3::dot(*wi, h));
+                    let denom = o + self.eta * i;
+                    let jacobian = self.eta * self.eta * i.abs() / (denom * denom);
+                    glass * self.ggx.pdf_visible(wo, &h) * jacobian
+                }
+                None => 0.0,
+            };
+        }
+        let h = *wo + *wi;
+        if h.near_zero() {
+            return 0.0;
+        }
+        let h = h.unit();
+        let reflect_jacobian = 1.0 / (4.0 * Vec3::dot(*wo, h).abs());
+        diffuse * wi.z / PI
+            + specular * self.ggx.pdf_visible(wo, &h) * reflect_jacobian
+            + clearcoat * gtr1(h.z, self.clearcoat_alpha()) * h.z * reflect_jacobian
+    }
+
+    fn sample(&self, wo: &Vec3) -> Option<Vec3> {
+        let p = self.lobe_probabilities(wo);
+        let mut u = random_f();
+        let mut lobe = 0;
+        while lobe < 3 && u >= p[lobe] {
+            u -= p[lobe];
+            lobe += 1;
+        }
+        let wi = match lobe {
+            0 => random_cosine_direction(),
+            1 => reflect_about(wo, &self.ggx.sample_visible(wo, random_f(), random_f())),
+            2 => refract_about(
+                wo,
+                &self.ggx.sample_visible(wo, random_f(), random_f()),
+                self.eta,
+            )?,
+            _ => {
+                let a2 = self.clearcoat_alpha() * self.clearcoat_alpha();
+                let cos = ((1.0 - a2.powf(1.0 - random_f())) / (1.0 - a2))
+                    .max(0.0)
+                    .sqrt();
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f();
+                reflect_about(wo, &Vec3::new(sin * phi.cos(), sin * phi.sin(), cos))
+            }
+        };
+        // Reflection lobes can still send a rough sample below the surface
+        if (lobe == 2) != (wi.z < 0.0) {
+            return None;
+        }
+        Some(wi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::rtweekend::seed_rng;
+
+    fn lobes(metallic: f32, roughness: f32, transmission: f32, clearcoat: f32) -> Lobes {
+        Lobes {
+            base: Color::new(0.8, 0.6, 0.4),
+            metallic,
+            roughness,
+            specular: 0.5,
+            specular_tint: 0.2,
+            sheen: 0.3,
+            sheen_tint: 0.5,
+            clearcoat,
+            clearcoat_gloss: 0.7,
+            transmission,
+            ggx: Ggx::from_roughness(roughness, 0.4),
+            eta: 1.5,
+        }
+    }
+
+    #[test]
+    fn test_sampling_matches_pdf() {
+        seed_rng(5);
+        let wo = Vec3::new(0.3, 0.1, 0.8).unit();
+        for lobes in &[
+            lobes(0.0, 0.5, 0.0, 0.0),
+            lobes(1.0, 0.6, 0.0, 1.0),
+            lobes(0.0, 0.7, 1.0, 0.0),
+            lobes(0.3, 0.8, 0.5, 0.5),
+        ] {
+            // The pdf integrates to the fraction of samples that are not rejected
+            let n = 100000;
+            let mut accepted = 0.0;
+            let mut albedo = Color::zero();
+            for _ in 0..n {
+                if let Some(wi) = lobes.sample(&wo) {
+                    accepted += 1.0 / n as f32;
+                    albedo += lobes.eval(&wo, &wi) * (wi.z.abs() / lobes.pdf(&wo, &wi) / n as f32);
+                }
+            }
+            let mut integral = 0.0;
+            for _ in 0..n {
+                let wi = random_in_unit_vector();
+                integral += lobes.pdf(&wo, &wi) * 4.0 * PI / n as f32;
+            }
+            assert!(
+                (integral - accepted).abs() < 0.03,
+                "{} {}",
+                integral,
+                accepted
+            );
+
+            // Energy is not created
+            assert!(
+                albedo.x < 1.05 && albedo.y < 1.05 && albedo.z < 1.05,
+                "{:?}",
+                albedo
+            );
+        }
+    }
+
+    #[test]
+    fn test_scatter_uses_bsdf_density() {
+        seed_rng(6);
+        let mut principled = Principled::new(Arc::new(SolidColor::new(0.8, 0.6, 0.4)));
+        principled.metallic = constant(0.4);
+        principled.clearcoat = constant(0.5);
+        principled.transmission = constant(0.3);
+        let mat_ptr: Arc<dyn Material> = Arc::new(principled);
+        let r_in = Ray::new(
+            &Point3::new(0.0, 1.0, 1.0),
+            &Vec3::new(0.1, -0.5, -1.0),
+            0.0,
+        );
+        let rec = HitRecord {
+            p: Point3::zero(),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            mat_ptr: mat_ptr.clone(),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+        };
+        let srec = mat_ptr.scatter(&r_in, &rec).unwrap();
+        assert!(!srec.is_specular);
+
+        // Any direction, sampled or from a light, is weighed by the same density
+        for _ in 0..1000 {
+            let direction = if random_f() < 0.5 {
+                srec.pdf_ptr.generate()
+            } else {
+                random_in_unit_vector()
+            };
+            if direction.near_zero() {
+                continue;
+            }
+            let scattered = Ray::new(&rec.p, &direction, 0.0);
+            let pdf = mat_ptr.scattering_pdf(&r_in, &rec, &scattered);
+            assert_eq!(srec.pdf_ptr.value(&direction), pdf);
+            let f = mat_ptr.eval(&r_in, &rec, &scattered);
+            assert!(f.x >= 0.0 && f.y >= 0.0 && f.z >= 0.0);
+            if pdf == 0.0 {
+                assert!(f.near_zero());
+            }
+        }
+    }
+}
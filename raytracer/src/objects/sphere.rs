@@ -1,9 +1,11 @@
 use super::material::Material;
+use crate::base::document::{number, vec3_value};
 use crate::base::onb::Onb;
 use crate::base::rtweekend::random_f;
 use crate::base::{ray::Ray, rtweekend::PI, vec3::*};
 use crate::hit::hittable::*;
 use crate::{base::rtweekend::INF, bvh::aabb::AABB};
+use serde_json::{json, Value};
 use std::mem::ManuallyDrop;
 use std::sync::Arc;
 
@@ -90,25 +92,48 @@ impl<T: 'static + Clone + Material + Sync + Send> Hittable for Sphere<T> {
         Some(outout_box)
     }
 
-    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f32 {
-        if let Some(rec) = self.hit(&Ray::new_default(o, v), 0.001, INF) {
-            let cos_theta_max =
-                (1.0 - self.radius * self.radius / (self.center - *o).squared_length()).sqrt();
-            let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
-            return 1.0 / solid_angle;
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(o, v, time), 0.001, INF) {
+            return cone_pdf(self.radius, (self.center - *o).squared_length());
         }
         0.0
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
         let direction = self.center - *o;
         let squared_distance = direction.squared_length();
         let uvw = Onb::build_from_w(&direction);
         uvw.local(&random_to_sphere(self.radius, squared_distance))
     }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "Sphere",
+            "center": vec3_value(&self.center),
+            "radius": number(self.radius),
+            "material": self.mat_ptr.to_document()?,
+        }))
+    }
 }
 
-fn random_to_sphere(radius: f32, squared_distance: f32) -> Vec3 {
+// Uniform over the cone of directions towards a sphere seen from outside it, or
+// over every direction from inside it
+pub fn cone_pdf(radius: f32, squared_distance: f32) -> f32 {
+    if squared_distance < radius * radius {
+        return 1.0 / (4.0 * PI);
+    }
+    let cos_theta_max = (1.0 - radius * radius / squared_distance).sqrt();
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+pub fn random_to_sphere(radius: f32, squared_distance: f32) -> Vec3 {
+    if squared_distance < radius * radius {
+        return random_in_unit_vector();
+    }
     let r1 = random_f();
     let r2 = random_f();
     let z = 1.0 + r2 * ((1.0 - radius * radius / squared_distance).sqrt() - 1.0);
@@ -1,16 +1,32 @@
 use std::{path::Path, sync::Arc};
 
+use crate::base::document::{number, vec3_value};
 use crate::{
     base::{
         rtweekend::*,
         vec3::{Color, Point3},
     },
-    perlin::Perlin,
+    objects::perlin::Perlin,
 };
 use image::{open, DynamicImage, GenericImageView};
+use serde_json::{json, Value};
 
 pub trait Texture: Sync + Send {
     fn value(&self, u: f32, v: f32, p: &Point3) -> Color;
+
+    fn to_document(&self) -> Option<Value> {
+        None
+    }
+}
+
+impl Texture for Arc<dyn Texture> {
+    fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
+        self.as_ref().value(u, v, p)
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        self.as_ref().to_document()
+    }
 }
 
 #[derive(Clone)]
@@ -22,6 +38,13 @@ impl Texture for SolidColor {
     fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
         self.color_value
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "ConstantTexture",
+            "color": vec3_value(&self.color_value),
+        }))
+    }
 }
 
 impl SolidColor {
@@ -59,6 +82,14 @@ impl<T: Texture, U: Texture> Texture for CheckerTexture<T, U> {
         }
         self.even.value(u, v, p)
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "CheckerTexture",
+            "t0": self.odd.to_document()?,
+            "t1": self.even.to_document()?,
+        }))
+    }
 }
 
 #[derive(Clone)]
@@ -80,10 +111,18 @@ impl Texture for NoiseTexture {
     fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
         Color::ones() * 0.5 * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(p, 7)).sin())
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "NoiseTexture",
+            "scale": number(self.scale),
+        }))
+    }
 }
 
 #[derive(Clone)]
 pub struct ImageTexture {
+    pub file_path: String,
     pub image: DynamicImage,
     pub width: u16,
     pub height: u16,
@@ -93,6 +132,7 @@ impl ImageTexture {
     pub fn new(file_path: &str) -> ImageTexture {
         let _image = open(Path::new(file_path)).unwrap();
         ImageTexture {
+            file_path: file_path.to_string(),
             image: _image.clone(),
             width: _image.dimensions().0 as u16,
             height: _image.dimensions().1 as u16,
@@ -124,4 +164,11 @@ impl Texture for ImageTexture {
             z: pixel[2] as f32 * color_scale,
         }
     }
+
+    fn to_document(&self) -> Option<Value> {
+        Some(json!({
+            "type": "ImageTexture",
+            "file": self.file_path,
+        }))
+    }
 }
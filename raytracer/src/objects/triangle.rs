@@ -0,0 +1,509 @@
+use std::sync::Arc;
+
+use crate::base::document::{number, vec3_value};
+use crate::base::{
+    ray::Ray,
+    rtweekend::{random_f, INF},
+    vec3::{Point3, Vec3},
+};
+use crate::bvh::aabb::AABB;
+use crate::bvh::bvh::Bvh;
+use crate::hit::hittable::*;
+use crate::objects::material::Material;
+use serde_json::{json, Value};
+
+#[derive(Clone)]
+pub struct Triangle<T: Material> {
+    mp: T,
+    v0: Point3,
+    v1: Point3,
+    v2: Point3,
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f32, f32); 3]>,
+}
+
+impl<T: Material> Triangle<T> {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, mp: T) -> Triangle<T> {
+        Triangle {
+            mp,
+            v0,
+            v1,
+            v2,
+            normals: None,
+            uvs: None,
+        }
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Triangle<T> {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f32, f32); 3]) -> Triangle<T> {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn area(&self) -> f32 {
+        area(&self.v0, &self.v1, &self.v2)
+    }
+}
+
+impl<T: 'static + Clone + Material + Sync + Send> Hittable for Triangle<T> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, b1, b2) = intersect(&self.v0, &self.v1, &self.v2, ray, t_min, t_max)?;
+        let geometric = Vec3::cross(self.v1 - self.v0, self.v2 - self.v0).unit();
+        let mut rec = HitRecord {
+            p: ray.at(t),
+            normal: Vec3::zero(),
+            mat_ptr: Arc::new(self.mp.clone()),
+            t,
+            u: b1,
+            v: b2,
+            front_face: false,
+        };
+        rec.set_face_normal(ray, &geometric);
+        if let Some(n) = &self.normals {
+            set_shading_normal(&mut rec, interpolate(n, b1, b2));
+        }
+        if let Some(uv) = &self.uvs {
+            let (u, v) = interpolate_uv(uv, b1, b2);
+            rec.u = u;
+            rec.v = v;
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        Some(bounds(&[self.v0, self.v1, self.v2]))
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(o, v, time), 0.001, INF) {
+            let normal = Vec3::cross(self.v1 - self.v0, self.v2 - self.v0).unit();
+            return solid_angle_pdf(rec.t, v, &normal, self.area());
+        }
+        0.0
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        sample_point(&self.v0, &self.v1, &self.v2) - o.clone()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let mut doc = json!({
+            "type": "Triangle",
+            "v0": vec3_value(&self.v0),
+            "v1": vec3_value(&self.v1),
+            "v2": vec3_value(&self.v2),
+            "material": self.mp.to_document()?,
+        });
+        if let Some(normals) = &self.normals {
+            doc["normals"] = normals.iter().map(vec3_value).collect();
+        }
+        if let Some(uvs) = &self.uvs {
+            doc["uvs"] = uvs.iter().map(uv_value).collect();
+        }
+        Some(doc)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshFace {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub faces: Vec<MeshFace>,
+}
+
+impl MeshData {
+    fn corners(&self, face: usize) -> (Point3, Point3, Point3) {
+        let [a, b, c] = self.faces[face].vertices;
+        (self.positions[a], self.positions[b], self.positions[c])
+    }
+}
+
+#[derive(Clone)]
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    mat_ptr: Arc<dyn Material>,
+    tree: Bvh,
+    cdf: Vec<f32>,
+    area: f32,
+}
+
+impl TriangleMesh {
+    pub fn new<T: 'static + Material>(data: MeshData, mp: T) -> TriangleMesh {
+        let data = Arc::new(data);
+        let mat_ptr: Arc<dyn Material> = Arc::new(mp);
+
+        let mut cdf = Vec::with_capacity(data.faces.len());
+        let mut area = 0.0;
+        let mut triangles: Vec<Arc<dyn Hittable>> = Vec::with_capacity(data.faces.len());
+        for face in 0..data.faces.len() {
+            let (p0, p1, p2) = data.corners(face);
+            area += self::area(&p0, &p1, &p2);
+            cdf.push(area);
+            triangles.push(Arc::new(MeshTriangle {
+                data: data.clone(),
+                mat_ptr: mat_ptr.clone(),
+                face,
+            }));
+        }
+        TriangleMesh {
+            data,
+            mat_ptr,
+            tree: Bvh::new(triangles, 0.0, 1.0),
+            cdf,
+            area,
+        }
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn area(&self) -> f32 {
+        self.area
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.tree.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.tree.bounding_box(time0, time1)
+    }
+
+    // Any face the line crosses could have been sampled, so each adds its density,
+    // taken with the geometric normal rather than an interpolated one
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f32) -> f32 {
+        let ray = Ray::new(o, v, time);
+        let mut pdf = 0.0;
+        self.tree.for_each_candidate(&ray, 0.001, INF, |face| {
+            let (p0, p1, p2) = self.data.corners(face);
+            if let Some((t, _, _)) = intersect(&p0, &p1, &p2, &ray, 0.001, INF) {
+                let normal = Vec3::cross(p1 - p0, p2 - p0).unit();
+                pdf += solid_angle_pdf(t, v, &normal, self.area);
+            }
+        });
+        pdf
+    }
+
+    fn random(&self, o: &Point3, time: f32) -> Vec3 {
+        if self.cdf.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        // Pick a face in proportion to its area, then a uniform point on it
+        let target = random_f() * self.area;
+        let face = match self
+            .cdf
+            .binary_search_by(|a| a.partial_cmp(&target).unwrap())
+        {
+            Ok(i) | Err(i) => i.min(self.cdf.len() - 1),
+        };
+        let (p0, p1, p2) = self.data.corners(face);
+        sample_point(&p0, &p1, &p2) - o.clone()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+
+    fn to_document(&self) -> Option<Value> {
+        let faces: Vec<Value> = self
+            .data
+            .faces
+            .iter()
+            .map(|face| {
+                let mut doc = json!({ "vertices": face.vertices });
+                if let Some(normals) = face.normals {
+                    doc["normals"] = json!(normals);
+                }
+                if let Some(uvs) = face.uvs {
+                    doc["uvs"] = json!(uvs);
+                }
+                doc
+            })
+            .collect();
+        Some(json!({
+            "type": "TriangleMesh",
+            "positions": self.data.positions.iter().map(vec3_value).collect::<Vec<_>>(),
+            "normals": self.data.normals.iter().map(vec3_value).collect::<Vec<_>>(),
+            "uvs": self.data.uvs.iter().map(uv_value).collect::<Vec<_>>(),
+            "faces": faces,
+            "material": self.mat_ptr.to_document()?,
+        }))
+    }
+}
+
+struct MeshTriangle {
+    data: Arc<MeshData>,
+    mat_ptr: Arc<dyn Material>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (p0, p1, p2) = self.data.corners(self.face);
+        let (t, b1, b2) = intersect(&p0, &p1, &p2, ray, t_min, t_max)?;
+        let face = &self.data.faces[self.face];
+        let geometric = Vec3::cross(p1 - p0, p2 - p0).unit();
+        let mut rec = HitRecord {
+            p: ray.at(t),
+            normal: Vec3::zero(),
+            mat_ptr: self.mat_ptr.clone(),
+            t,
+            u: b1,
+            v: b2,
+            front_face: false,
+        };
+        rec.set_face_normal(ray, &geometric);
+        if let Some([a, b, c]) = face.normals {
+            let n = &self.data.normals;
+            set_shading_normal(&mut rec, interpolate(&[n[a], n[b], n[c]], b1, b2));
+        }
+        if let Some([a, b, c]) = face.uvs {
+            let uv = &self.data.uvs;
+            let (u, v) = interpolate_uv(&[uv[a], uv[b], uv[c]], b1, b2);
+            rec.u = u;
+            rec.v = v;
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        let (p0, p1, p2) = self.data.corners(self.face);
+        Some(bounds(&[p0, p1, p2]))
+    }
+}
+
+// Möller–Trumbore; returns t and the barycentric weights of v1 and v2
+fn intersect(
+    v0: &Point3,
+    v1: &Point3,
+    v2: &Point3,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    let e1 = *v1 - *v0;
+    let e2 = *v2 - *v0;
+    let pvec = Vec3::cross(ray.dir, e2);
+    let det = Vec3::dot(e1, pvec);
+    if det.abs() < 1e-8 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = ray.orig - *v0;
+    let b1 = Vec3::dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = Vec3::cross(tvec, e1);
+    let b2 = Vec3::dot(ray.dir, qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = Vec3::dot(e2, qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+fn interpolate(n: &[Vec3; 3], b1: f32, b2: f32) -> Vec3 {
+    (n[0] * (1.0 - b1 - b2) + n[1] * b1 + n[2] * b2).unit()
+}
+
+fn interpolate_uv(uv: &[(f32, f32); 3], b1: f32, b2: f32) -> (f32, f32) {
+    let b0 = 1.0 - b1 - b2;
+    (
+        uv[0].0 * b0 + uv[1].0 * b1 + uv[2].0 * b2,
+        uv[0].1 * b0 + uv[1].1 * b1 + uv[2].1 * b2,
+    )
+}
+
+// Keeps the shading normal on the same side as the geometric one
+fn set_shading_normal(rec: &mut HitRecord, n: Vec3) {
+    rec.normal = if Vec3::dot(n, rec.normal) < 0.0 {
+        -n
+    } else {
+        n
+    };
+}
+
+fn area(v0: &Point3, v1: &Point3, v2: &Point3) -> f32 {
+    0.5 * Vec3::cross(*v1 - *v0, *v2 - *v0).length()
+}
+
+fn bounds(points: &[Point3; 3]) -> AABB {
+    let mut min = points[0];
+    let mut max = points[0];
+    for p in &points[1..] {
+        for i in 0..3 {
+            min[i] = min[i].min(p[i]);
+            max[i] = max[i].max(p[i]);
+        }
+    }
+    // Pad so axis-aligned triangles still have a non-zero box
+    AABB::new(&(min - 0.0001), &(max + 0.0001))
+}
+
+fn sample_point(v0: &Point3, v1: &Point3, v2: &Point3) -> Point3 {
+    let s = random_f().sqrt();
+    let b1 = random_f() * s;
+    let b0 = 1.0 - s;
+    *v0 * b0 + *v1 * b1 + *v2 * (1.0 - b0 - b1)
+}
+
+fn uv_value(uv: &(f32, f32)) -> Value {
+    json!({
+        "u": number(uv.0),
+        "v": number(uv.1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::rtweekend::{seed_rng, PI};
+    use crate::base::vec3::random_in_unit_vector;
+    use crate::objects::material::Lambertian;
+    use crate::objects::texture::SolidColor;
+
+    fn quad() -> TriangleMesh {
+        let data = MeshData {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(2.0, 0.0, 0.0),
+                Point3::new(2.0, 2.0, 0.0),
+                Point3::new(0.0, 2.0, 0.0),
+            ],
+            normals: Vec::new(),
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            faces: vec![
+                MeshFace {
+                    vertices: [0, 1, 2],
+                    normals: None,
+                    uvs: Some([0, 1, 2]),
+                },
+                MeshFace {
+                    vertices: [0, 2, 3],
+                    normals: None,
+                    uvs: Some([0, 2, 3]),
+                },
+            ],
+        };
+        TriangleMesh::new(data, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn test_triangle_barycentric() {
+        let triangle = Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        );
+        let ray = Ray::new(
+            &Point3::new(0.25, 0.5, 1.0),
+            &Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let rec = triangle.hit(&ray, 0.001, INF).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert_eq!((rec.u, rec.v), (0.25, 0.5));
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+
+        let miss = Ray::new(
+            &Point3::new(0.75, 0.5, 1.0),
+            &Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        assert!(triangle.hit(&miss, 0.001, INF).is_none());
+    }
+
+    #[test]
+    fn test_mesh_uv_and_sampling() {
+        let mesh = quad();
+        assert_eq!(mesh.area(), 4.0);
+
+        let ray = Ray::new(&Point3::new(0.5, 1.5, 1.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = mesh.hit(&ray, 0.001, INF).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-6 && (rec.v - 0.75).abs() < 1e-6);
+
+        let o = Point3::new(1.0, 1.0, 3.0);
+        for _ in 0..100 {
+            let v = mesh.random(&o, 0.0);
+            assert!(mesh.pdf_value(&o, &v, 0.0) > 0.0);
+        }
+        assert_eq!(mesh.pdf_value(&o, &Vec3::new(0.0, 0.0, 1.0), 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_closed_mesh_pdf() {
+        // A cube between (-1, -1, 1.5) and (1, 1, 3), smoothed by normals pointing
+        // out from its centre
+        let centre = Point3::new(0.0, 0.0, 2.25);
+        let mut positions = Vec::new();
+        for i in 0..8 {
+            let corner = |bit: usize, lo: f32, hi: f32| if i & bit == 0 { lo } else { hi };
+            positions.push(Point3::new(
+                corner(1, -1.0, 1.0),
+                corner(2, -1.0, 1.0),
+                corner(4, 1.5, 3.0),
+            ));
+        }
+        let normals = positions.iter().map(|p| (*p - centre).unit()).collect();
+        let quads = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let mut faces = Vec::new();
+        for [a, b, c, d] in quads.iter() {
+            for vertices in &[[*a, *b, *c], [*a, *c, *d]] {
+                faces.push(MeshFace {
+                    vertices: *vertices,
+                    normals: Some(*vertices),
+                    uvs: None,
+                });
+            }
+        }
+        let data = MeshData {
+            positions,
+            normals,
+            uvs: Vec::new(),
+            faces,
+        };
+        let mesh = TriangleMesh::new(data, Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)));
+
+        seed_rng(4);
+        let o = Point3::zero();
+        let n = 50000;
+        let mut estimate = 0.0;
+        for _ in 0..n {
+            let v = random_in_unit_vector();
+            estimate += mesh.pdf_value(&o, &v, 0.0) * 4.0 * PI / n as f32;
+        }
+        assert!((estimate - 1.0).abs() < 0.05, "{}", estimate);
+    }
+}
@@ -10,13 +10,15 @@ use super::Pdf;
 #[derive(Clone)]
 pub struct HittablePdf<T: Hittable> {
     pub orig: Point3,
+    pub time: f32,
     pub ptr: T,
 }
 
 impl<T: Hittable> HittablePdf<T> {
-    pub fn new(p: T, origin: Point3) -> HittablePdf<T> {
+    pub fn new(p: T, origin: Point3, time: f32) -> HittablePdf<T> {
         HittablePdf {
             orig: origin,
+            time,
             ptr: p,
         }
     }
@@ -24,10 +26,10 @@ impl<T: Hittable> HittablePdf<T> {
 
 impl<T: Hittable> Pdf for HittablePdf<T> {
     fn value(&self, direction: &Vec3) -> f32 {
-        self.ptr.pdf_value(&self.orig, &direction)
+        self.ptr.pdf_value(&self.orig, &direction, self.time)
     }
 
     fn generate(&self) -> Vec3 {
-        self.ptr.random(&self.orig)
+        self.ptr.random(&self.orig, self.time)
     }
 }
@@ -1,11 +1,33 @@
 pub mod cosine_pdf;
 pub mod hittable_pdf;
 pub mod mixture_pdf;
+pub mod sphere_pdf;
 
 use crate::base::Vec3;
 
 pub trait Pdf {
     fn value(&self, direction: &Vec3) -> f32;
 
+    // A zero vector marks a rejected sample, which contributes nothing
     fn generate(&self) -> Vec3;
 }
+
+// Veach's power heuristic with beta = 2, the weight of a sample drawn with density
+// `f` when another strategy would have drawn it with density `g`
+pub fn power_heuristic(f: f32, g: f32) -> f32 {
+    let (f2, g2) = (f * f, g * g);
+    if f2 + g2 == 0.0 {
+        return 0.0;
+    }
+    f2 / (f2 + g2)
+}
+
+impl<P: Pdf + ?Sized> Pdf for Box<P> {
+    fn value(&self, direction: &Vec3) -> f32 {
+        self.as_ref().value(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.as_ref().generate()
+    }
+}
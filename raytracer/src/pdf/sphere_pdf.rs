@@ -0,0 +1,23 @@
+use crate::base::{rtweekend::PI, vec3::random_in_unit_vector, Vec3};
+
+use super::Pdf;
+
+// Uniform over all directions, as scattered by an isotropic medium
+#[derive(Clone)]
+pub struct SpherePdf {}
+
+impl SpherePdf {
+    pub fn new() -> SpherePdf {
+        SpherePdf {}
+    }
+}
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        random_in_unit_vector()
+    }
+}
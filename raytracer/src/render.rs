@@ -0,0 +1,463 @@
+use indicatif::ProgressBar;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use threadpool::ThreadPool;
+
+use crate::base::{color::luminance, *};
+use crate::framebuffer::Accumulator;
+use crate::hit::{hittable::Hittable, hittable_list::HittableList};
+use crate::light::Lights;
+use crate::scene::ray_color;
+
+pub struct Renderer {
+    pub world: Arc<dyn Hittable>,
+    pub lights: Arc<Lights>,
+    pub camera: Camera,
+    pub width: u32,
+    pub height: u32,
+    pub max_depth: u16,
+    pub seed: u64,
+}
+
+impl Renderer {
+    // Radiance of one camera sample; (x, y) counts rows from the top of the image
+    pub fn sample(&self, x: u32, y: u32, s: u32) -> Color {
+        seed_sample(self.seed, y as u64 * self.width as u64 + x as u64, s as u64);
+        let u = (x as f32 + random_f()) / (self.width - 1) as f32;
+        let v = (self.height as f32 - y as f32 + random_f()) / (self.height - 1) as f32;
+        let r = self.camera.get_ray(u, v);
+        ray_color(&r, self.world.as_ref(), &self.lights, self.max_depth)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Adaptive {
+    pub threshold: f32,
+    pub min_spp: u32,
+}
+
+// How many samples each pixel gets per pass and in total. Without adaptive
+// sampling every pixel goes to `spp`; with it a pixel stops once it has
+// `min_spp` samples and its relative error is below the threshold
+#[derive(Clone, Copy, Debug)]
+pub struct SampleSchedule {
+    pub spp: u32,
+    pub pass_spp: u32,
+    pub adaptive: Option<Adaptive>,
+}
+
+impl SampleSchedule {
+    // Sample indices the next pass takes for a pixel; continuing from its
+    // current count means a pass renders the same whether or not the earlier
+    // ones came from a checkpoint
+    pub fn samples(&self, acc: &Accumulator, x: u32, y: u32) -> Range<u32> {
+        let start = acc.samples(x, y);
+        if let Some(adaptive) = &self.adaptive {
+            if start >= adaptive.min_spp && acc.relative_error(x, y) <= adaptive.threshold {
+                return start..start;
+            }
+        }
+        start..self.spp.min(start + self.pass_spp).max(start)
+    }
+
+    // Upper bound on the passes left, for the progress bar
+    pub fn passes(&self, acc: &Accumulator) -> u32 {
+        let remaining = self.spp.saturating_sub(acc.min_samples());
+        (remaining + self.pass_spp - 1) / self.pass_spp
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub x: Range<u32>,
+    pub y: Range<u32>,
+}
+
+// Square tiles covering the image, ordered along a Hilbert curve so consecutive
+// tiles stay close together on screen
+pub fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let size = size.max(1);
+    let (cols, rows) = ((width + size - 1) / size, (height + size - 1) / size);
+    let order = cols.max(rows).next_power_of_two();
+    let mut tiles = Vec::with_capacity((cols * rows) as usize);
+    for ty in 0..rows {
+        for tx in 0..cols {
+            tiles.push((
+                hilbert_index(order, tx, ty),
+                Tile {
+                    x: tx * size..width.min((tx + 1) * size),
+                    y: ty * size..height.min((ty + 1) * size),
+                },
+            ));
+        }
+    }
+    tiles.sort_by_key(|(d, _)| *d);
+    tiles.into_iter().map(|(_, tile)| tile).collect()
+}
+
+// Distance of (x, y) along the Hilbert curve filling an n by n grid, n a power of two
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so the curve inside it starts and ends at the right corners
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+// Renders one pass of the schedule and returns how many samples it took; zero
+// means every pixel is done. Each pool thread keeps pulling the next tile from a
+// shared counter, so a slow tile holds up only the thread that drew it
+pub fn render_pass(
+    renderer: &Arc<Renderer>,
+    pool: &ThreadPool,
+    acc: &mut Accumulator,
+    schedule: &SampleSchedule,
+    tiles: &Arc<Vec<Tile>>,
+    bar: &ProgressBar,
+) -> u64 {
+    let width = renderer.width;
+    let mut ranges = Vec::with_capacity((width * renderer.height) as usize);
+    for y in 0..renderer.height {
+        for x in 0..width {
+            ranges.push(schedule.samples(acc, x, y));
+        }
+    }
+    let ranges = Arc::new(ranges);
+    let next = Arc::new(AtomicUsize::new(0));
+
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..pool.max_count().min(tiles.len()) {
+        let tx = tx.clone();
+        let renderer = renderer.clone();
+        let tiles = tiles.clone();
+        let ranges = ranges.clone();
+        let next = next.clone();
+        pool.execute(move || loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let tile = match tiles.get(index) {
+                Some(tile) => tile,
+                None => break,
+            };
+            let mut pixels = Vec::with_capacity(tile.x.len() * tile.y.len());
+            for y in tile.y.clone() {
+                for x in tile.x.clone() {
+                    let samples = ranges[(y * width + x) as usize].clone();
+                    let mut color = Color::zero();
+                    let mut sum_sq = 0.0;
+                    for s in samples.clone() {
+                        let c = renderer.sample(x, y, s);
+                        color += c;
+                        sum_sq += luminance(&c) * luminance(&c);
+                    }
+                    pixels.push((color, sum_sq, samples.end - samples.start));
+                }
+            }
+            tx.send((index, pixels)).expect("FAILED IN SENDING");
+        });
+    }
+
+    let mut taken = 0;
+    for (index, pixels) in rx.iter().take(tiles.len()) {
+        let tile = &tiles[index];
+        let mut pixels = pixels.into_iter();
+        for y in tile.y.clone() {
+            for x in tile.x.clone() {
+                let (color, sum_sq, count) = pixels.next().unwrap();
+                acc.add(x, y, color, sum_sq, count);
+                taken += count as u64;
+            }
+        }
+        bar.inc(1);
+    }
+    taken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::rtweekend::seed_rng;
+    use crate::bvh::bvh::Bvh;
+    use crate::hit::transform::Transform;
+    use crate::light::environment::{EnvironmentMap, UniformEnvironment};
+    use crate::light::punctual::{DirectionalLight, PointLight};
+    use crate::objects::arrect::XZRect;
+    use crate::objects::material::{DiffuseLight, Lambertian};
+    use crate::objects::sphere::Sphere;
+    use crate::objects::texture::SolidColor;
+    use crate::scene::find_scene;
+    use std::thread;
+
+    fn cornell(seed: u64) -> Renderer {
+        preset("cornell_box", seed)
+    }
+
+    fn preset(name: &str, seed: u64) -> Renderer {
+        let scene = find_scene(name).unwrap().describe(1.0);
+        let lights = Lights::new(scene.world.lights(), scene.environment, scene.lights);
+        Renderer {
+            world: Arc::new(Bvh::new(scene.world.objects, 0.0, 1.0)),
+            lights: Arc::new(lights),
+            camera: scene.camera,
+            width: 16,
+            height: 16,
+            max_depth: 10,
+            seed,
+        }
+    }
+
+    fn black() -> Arc<UniformEnvironment> {
+        Arc::new(UniformEnvironment::new(Color::zero()))
+    }
+
+    fn pixels(renderer: &Renderer, rows: std::ops::Range<u32>) -> Vec<Color> {
+        let mut colors = Vec::new();
+        for y in rows {
+            for x in 0..renderer.width {
+                for s in 0..4 {
+                    colors.push(renderer.sample(x, y, s));
+                }
+            }
+        }
+        colors
+    }
+
+    #[test]
+    fn test_samples_are_reproducible() {
+        let renderer = Arc::new(cornell(42));
+        let expected = pixels(&renderer, 0..16);
+
+        // Split the rows over threads in the opposite order; nothing may change
+        let handles: Vec<_> = (0..4)
+            .rev()
+            .map(|band| {
+                let renderer = renderer.clone();
+                thread::spawn(move || pixels(&renderer, band * 4..band * 4 + 4))
+            })
+            .collect();
+        let mut split = Vec::new();
+        for handle in handles.into_iter().rev() {
+            split.extend(handle.join().unwrap());
+        }
+        assert_eq!(split, expected);
+
+        assert_ne!(pixels(&cornell(43), 0..16), expected);
+    }
+
+    #[test]
+    fn test_adaptive_schedule() {
+        let schedule = SampleSchedule {
+            spp: 64,
+            pass_spp: 8,
+            adaptive: Some(Adaptive {
+                threshold: 0.05,
+                min_spp: 8,
+            }),
+        };
+        let mut acc = Accumulator::new(2, 1);
+        assert_eq!(schedule.samples(&acc, 0, 0), 0..8);
+        for i in 0..8 {
+            acc.add(0, 0, Color::new(0.5, 0.5, 0.5), 0.25, 1);
+            let v = (i % 2) as f32;
+            acc.add(1, 0, Color::new(v, v, v), v * v, 1);
+        }
+        assert_eq!(schedule.samples(&acc, 0, 0), 8..8);
+        assert_eq!(schedule.samples(&acc, 1, 0), 8..16);
+        assert_eq!(schedule.passes(&acc), 7);
+
+        let uniform = SampleSchedule {
+            adaptive: None,
+            ..schedule
+        };
+        assert_eq!(uniform.samples(&acc, 0, 0), 8..16);
+    }
+
+    #[test]
+    fn test_tiles_cover_image() {
+        let cover = tiles(70, 37, 16);
+        assert_eq!(cover.len(), 5 * 3);
+        let mut covered = vec![0; 70 * 37];
+        for tile in &cover {
+            assert!(tile.x.len() <= 16 && tile.y.len() <= 16);
+            for y in tile.y.clone() {
+                for x in tile.x.clone() {
+                    covered[y as usize * 70 + x as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&n| n == 1));
+
+        // Consecutive tiles along the curve share an edge
+        let grid = tiles(64, 64, 8);
+        for pair in grid.windows(2) {
+            let dx = (pair[0].x.start as i32 - pair[1].x.start as i32).abs();
+            let dy = (pair[0].y.start as i32 - pair[1].y.start as i32).abs();
+            assert_eq!(dx + dy, 8);
+        }
+    }
+
+    #[test]
+    fn test_direct_lighting() {
+        // A diffuse floor under a sphere light of radius r at height d reflects
+        // albedo * emission * (r / d)^2 straight below it
+        let light: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            &Point3::new(0.0, 4.0, 0.0),
+            1.0,
+            DiffuseLight::new(SolidColor::new(4.0, 4.0, 4.0)),
+        ));
+        let mut world = HittableList::new();
+        world.add(light.clone());
+        world.add(Arc::new(XZRect::new(
+            -100.0,
+            100.0,
+            -100.0,
+            100.0,
+            0.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        )));
+        let mut objects = HittableList::new();
+        objects.add(light);
+        let lights = Lights::new(objects, black(), Vec::new());
+
+        seed_rng(7);
+        let ray = Ray::new(
+            &Point3::new(0.0, 2.0, 2.0),
+            &Vec3::new(0.0, -2.0, -2.0),
+            0.0,
+        );
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += ray_color(&ray, &world, &lights, 2).x / n as f32;
+        }
+        assert!((sum - 0.125).abs() < 0.004);
+    }
+
+    #[test]
+    fn test_punctual_lighting() {
+        // A diffuse floor reflects albedo / pi * I cos / d^2 of a point light,
+        // and the same light shining past a blocker leaves it dark
+        let mut world = HittableList::new();
+        world.add(Arc::new(XZRect::new(
+            -100.0,
+            100.0,
+            -100.0,
+            100.0,
+            0.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        )));
+        let point = PointLight::new(Point3::new(0.0, 2.0, 0.0), Color::new(4.0, 4.0, 4.0), None);
+        let lights = Lights::new(HittableList::new(), black(), vec![Arc::new(point)]);
+        let ray = Ray::new(
+            &Point3::new(0.0, 1.0, 1.0),
+            &Vec3::new(0.0, -1.0, -1.0),
+            0.0,
+        );
+        let color = ray_color(&ray, &world, &lights, 1);
+        assert!((color.x - 0.5 / PI).abs() < 1e-4);
+
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 1.0, 0.0),
+            0.5,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        )));
+        let sun = DirectionalLight::new(Vec3::new(0.0, 1.0, 0.0), Color::new(2.0, 2.0, 2.0), 0.0);
+        let lights = Lights::new(HittableList::new(), black(), vec![Arc::new(sun)]);
+        assert_eq!(ray_color(&ray, &world, &lights, 1).x, 0.0);
+    }
+
+    #[test]
+    fn test_environment_lighting() {
+        // Under a white sky a diffuse floor reflects exactly its albedo, also when
+        // light sampling splits its samples between the sky and another light
+        let mut world = HittableList::new();
+        world.add(Arc::new(XZRect::new(
+            -1000.0,
+            1000.0,
+            -1000.0,
+            1000.0,
+            0.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        )));
+        let sky = Arc::new(EnvironmentMap::from_pixels(
+            "",
+            16,
+            8,
+            vec![Color::new(1.0, 1.0, 1.0); 16 * 8],
+            0.0,
+            1.0,
+        ));
+        let mut other = HittableList::new();
+        other.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 500.0, 0.0),
+            1.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        )));
+        let ray = Ray::new(
+            &Point3::new(0.0, 1.0, 1.0),
+            &Vec3::new(0.0, -1.0, -1.0),
+            0.0,
+        );
+        for lights in vec![
+            Lights::new(HittableList::new(), sky.clone(), Vec::new()),
+            Lights::new(other, sky, Vec::new()),
+        ] {
+            seed_rng(3);
+            let n = 20000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                sum += ray_color(&ray, &world, &lights, 2).x / n as f32;
+            }
+            assert!((sum - 0.5).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_scene_lights() {
+        assert_eq!(cornell(0).lights.objects.objects.len(), 1);
+
+        // Lights inside a transformed group come out already placed
+        let mut group = HittableList::new();
+        group.add(Arc::new(Sphere::new(
+            &Point3::zero(),
+            1.0,
+            DiffuseLight::new(SolidColor::new(1.0, 1.0, 1.0)),
+        )));
+        group.add(Arc::new(Sphere::new(
+            &Point3::zero(),
+            2.0,
+            Lambertian::new(SolidColor::new(0.5, 0.5, 0.5)),
+        )));
+        let mut world = HittableList::new();
+        world.add(Arc::new(Transform::new(
+            Arc::new(group),
+            Mat4::translate(Vec3::new(0.0, 5.0, 0.0)),
+        )));
+        let lights = world.lights();
+        assert_eq!(lights.objects.len(), 1);
+        let bbox = lights.objects[0].bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(bbox.minimum.y, 4.0);
+
+        // Without lights only the BSDF samples remain
+        let renderer = preset("random_scene", 0);
+        assert!(renderer.lights.objects.objects.is_empty());
+        for color in pixels(&renderer, 0..4) {
+            assert!(color.x.is_finite() && color.y.is_finite() && color.z.is_finite());
+        }
+    }
+}
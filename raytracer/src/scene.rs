@@ -1,18 +1,171 @@
-use crate::base::{ray::*, rtweekend::*, vec3::*};
+use crate::base::{camera::Camera, ray::*, rtweekend::*, vec3::*};
 use crate::bvh::bvh::*;
 use crate::hit::{hittable::*, hittable_list::*};
+use crate::light::environment::{EnvironmentMap, UniformEnvironment};
+use crate::light::{Environment, Lights};
+use crate::loader::SceneDescription;
 use crate::objects::{
     arrect::*, constant_medium::ConstantMedium, material::*, moving_sphere::*, sphere::*,
     texture::*,
 };
 use crate::pdf::cosine_pdf::{self, CosinePdf};
-use crate::pdf::hittable_pdf::HittablePdf;
-use crate::pdf::mixture_pdf::MixturePdf;
-use crate::pdf::Pdf;
+use crate::pdf::{power_heuristic, Pdf};
 use std::clone;
 use std::ops::Deref;
 use std::sync::Arc;
 
+pub struct ScenePreset {
+    pub name: &'static str,
+    pub build: fn() -> HittableList,
+    pub background: Color,
+    // Replaces the uniform background when set
+    pub environment: Option<fn() -> Arc<dyn Environment>>,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vfov: f32,
+    pub aperture: f32,
+}
+
+impl ScenePreset {
+    fn new(
+        name: &'static str,
+        build: fn() -> HittableList,
+        background: Color,
+        lookfrom: Point3,
+        lookat: Point3,
+        vfov: f32,
+    ) -> ScenePreset {
+        ScenePreset {
+            name,
+            build,
+            background,
+            environment: None,
+            lookfrom,
+            lookat,
+            vfov,
+            aperture: 0.0,
+        }
+    }
+
+    pub fn describe(&self, aspect_ratio: f32) -> SceneDescription {
+        SceneDescription {
+            world: (self.build)(),
+            camera: Camera::new(
+                self.lookfrom,
+                self.lookat,
+                Vec3::new(0.0, 1.0, 0.0),
+                self.vfov,
+                aspect_ratio,
+                self.aperture,
+                10.0,
+                0.0,
+                1.0,
+            ),
+            aspect_ratio,
+            environment: match self.environment {
+                Some(build) => build(),
+                None => Arc::new(UniformEnvironment::new(self.background)),
+            },
+            lights: Vec::new(),
+        }
+    }
+}
+
+pub fn scene_presets() -> Vec<ScenePreset> {
+    let sky = Color::new(0.70, 0.80, 1.00);
+    let outdoor = Point3::new(13.0, 2.0, 3.0);
+    let cornell_from = Point3::new(278.0, 278.0, -800.0);
+    let cornell_at = Point3::new(278.0, 278.0, 0.0);
+
+    vec![
+        ScenePreset {
+            aperture: 0.1,
+            ..ScenePreset::new(
+                "random_scene",
+                random_scene,
+                sky,
+                outdoor,
+                Point3::zero(),
+                20.0,
+            )
+        },
+        ScenePreset::new(
+            "two_checker_spheres",
+            two_checker_spheres,
+            sky,
+            outdoor,
+            Point3::zero(),
+            20.0,
+        ),
+        ScenePreset::new(
+            "two_perlin_spheres",
+            two_perlin_spheres,
+            sky,
+            outdoor,
+            Point3::zero(),
+            20.0,
+        ),
+        ScenePreset::new("earth", earth, sky, outdoor, Point3::zero(), 20.0),
+        ScenePreset::new(
+            "simple_light",
+            simple_light,
+            Color::zero(),
+            Point3::new(26.0, 3.0, 6.0),
+            Point3::new(0.0, 2.0, 0.0),
+            20.0,
+        ),
+        ScenePreset::new(
+            "cornell_box",
+            cornell_box,
+            Color::zero(),
+            cornell_from,
+            cornell_at,
+            40.0,
+        ),
+        ScenePreset::new(
+            "cornell_smoke",
+            cornell_smoke,
+            sky,
+            cornell_from,
+            cornell_at,
+            40.0,
+        ),
+        ScenePreset::new(
+            "final_scene",
+            final_scene,
+            Color::zero(),
+            Point3::new(478.0, 278.0, -600.0),
+            cornell_at,
+            40.0,
+        ),
+        ScenePreset {
+            environment: Some(star_field),
+            ..ScenePreset::new(
+                "solar_system",
+                solar_system,
+                Color::zero(),
+                Point3::new(0.0, 600.0, -900.0),
+                Point3::new(0.0, 200.0, 0.0),
+                40.0,
+            )
+        },
+        ScenePreset::new(
+            "pic",
+            pic,
+            Color::new(0.90, 0.90, 0.97),
+            cornell_from,
+            cornell_at,
+            40.0,
+        ),
+    ]
+}
+
+pub fn find_scene(name: &str) -> Option<ScenePreset> {
+    scene_presets()
+        .into_iter()
+        .find(|preset| preset.name == name)
+}
+
 pub fn random_scene() -> HittableList {
     let mut world = HittableList::new();
 
@@ -85,40 +238,97 @@ pub fn random_scene() -> HittableList {
     world
 }
 
-pub fn ray_color(
+pub fn ray_color(ray: &Ray, world: &dyn Hittable, lights: &Lights, depth: u16) -> Color {
+    trace(ray, world, lights, depth, None)
+}
+
+// `bsdf_pdf` is the density with which the previous bounce sampled `ray`. Light
+// sampling at that bounce could have found the same emitter, so the emission is
+// weighted against it with the power heuristic
+fn trace(
     ray: &Ray,
-    background: &Color,
-    world: &HittableList,
-    lights: &Arc<HittableList>,
+    world: &dyn Hittable,
+    lights: &Lights,
     depth: u16,
+    bsdf_pdf: Option<f32>,
 ) -> Color {
     if depth == 0 {
         return Color::zero();
     }
 
-    if let Some(rec) = world.hit(ray, 0.001, INF) {
-        let emitted = rec.mat_ptr.emitted(ray, &rec.clone(), rec.u, rec.v, &rec.p);
-        if let Some(mut srec) = rec.mat_ptr.scatter(ray, &rec) {
-            if srec.is_specular {
-                return Vec3::elemul(
-                    srec.attenuation,
-                    ray_color(&srec.specular_ray, background, world, lights, depth - 1),
-                );
+    let rec = match world.hit(ray, 0.001, INF) {
+        Some(rec) => rec,
+        None => {
+            let radiance = lights.environment.value(&ray.dir);
+            return radiance * light_weight(ray, &radiance, lights, bsdf_pdf);
+        }
+    };
+    let mut emitted = rec.mat_ptr.emitted(ray, &rec, rec.u, rec.v, &rec.p);
+    emitted *= light_weight(ray, &emitted, lights, bsdf_pdf);
+    let srec = match rec.mat_ptr.scatter(ray, &rec) {
+        Some(srec) => srec,
+        None => return emitted,
+    };
+    if srec.is_specular {
+        let incoming = trace(&srec.specular_ray, world, lights, depth - 1, None);
+        return emitted + Vec3::elemul(srec.attenuation, incoming);
+    }
+
+    // Next-event estimation: a shadow ray towards a point sampled on the lights,
+    // counting whatever emitter it reaches first, or the environment if it escapes
+    let mut direct = Color::zero();
+    let to_light = lights.random(&rec.p, ray.tm);
+    let light_pdf = lights.pdf_value(&rec.p, &to_light, ray.tm);
+    if light_pdf > 0.0 {
+        let shadow = Ray::new(&rec.p, &to_light, ray.tm);
+        let radiance = match world.hit(&shadow, 0.001, INF) {
+            Some(light_rec) => light_rec.mat_ptr.emitted(
+                &shadow,
+                &light_rec,
+                light_rec.u,
+                light_rec.v,
+                &light_rec.p,
+            ),
+            None => lights.environment.value(&to_light),
+        };
+        if !radiance.near_zero() {
+            let f = Vec3::elemul(srec.attenuation, rec.mat_ptr.eval(ray, &rec, &shadow));
+            let weight = power_heuristic(light_pdf, srec.pdf_ptr.value(&to_light));
+            direct = Vec3::elemul(f, radiance) * (weight / light_pdf);
+        }
+    }
+    // Punctual lights can't be found any other way, so each gets a shadow ray
+    for light in &lights.punctual {
+        if let Some(sample) = light.sample(&rec.p) {
+            let shadow = Ray::new(&rec.p, &sample.direction, ray.tm);
+            if world.hit(&shadow, 0.001, sample.distance).is_none() {
+                let f = Vec3::elemul(srec.attenuation, rec.mat_ptr.eval(ray, &rec, &shadow));
+                direct += Vec3::elemul(f, sample.radiance);
             }
+        }
+    }
 
-            let light_ptr = HittablePdf::new(lights.deref().clone(), rec.p);
-            let p = MixturePdf::new(light_ptr, srec.pdf_ptr);
-            let scattered = Ray::new(&rec.p, &p.generate(), ray.tm);
-            let pdf_val = p.value(&scattered.dir);
+    let direction = srec.pdf_ptr.generate();
+    if direction.near_zero() {
+        return emitted + direct;
+    }
+    let scattered = Ray::new(&rec.p, &direction, ray.tm);
+    let pdf = srec.pdf_ptr.value(&direction);
+    if pdf <= 0.0 {
+        return emitted + direct;
+    }
+    let f = Vec3::elemul(srec.attenuation, rec.mat_ptr.eval(ray, &rec, &scattered));
+    let incoming = trace(&scattered, world, lights, depth - 1, Some(pdf));
+    emitted + direct + Vec3::elemul(f, incoming) / pdf
+}
 
-            let multiply = ray_color(&scattered, background, world, lights, depth - 1)
-                * rec.mat_ptr.scattering_pdf(ray, &rec.clone(), &scattered);
-            let add = Color::elemul(srec.attenuation, multiply) / pdf_val;
-            return emitted + add;
+// MIS weight of radiance that a BSDF sample reached along `ray`
+fn light_weight(ray: &Ray, radiance: &Color, lights: &Lights, bsdf_pdf: Option<f32>) -> f32 {
+    match bsdf_pdf {
+        Some(pdf) if !radiance.near_zero() => {
+            power_heuristic(pdf, lights.pdf_value(&ray.orig, &ray.dir, ray.tm))
         }
-        emitted
-    } else {
-        background.clone()
+        _ => 1.0,
     }
 }
 
@@ -620,7 +830,7 @@ pub fn pic() -> HittableList {
     //     }
     // }
     let mut ret_objects = HittableList::new();
-    ret_objects.add(Arc::new(BvhNode::new_with_list(&mut objects, 0., 0.)));
+    ret_objects.add(Arc::new(Bvh::new_with_list(&objects, 0., 0.)));
     ret_objects
 }
 
@@ -736,7 +946,7 @@ pub fn final_scene() -> HittableList {
     }
     let mut objects = HittableList::new();
 
-    objects.add(Arc::new(BvhNode::new_with_list(&mut boxes1, 0.0, 1.0)));
+    objects.add(Arc::new(Bvh::new_with_list(&boxes1, 0.0, 1.0)));
 
     let light = DiffuseLight::new(SolidColor::new_with_color(Color::new(7.0, 7.0, 7.0)));
     objects.add(Arc::new(XZRect::new(
@@ -815,7 +1025,7 @@ pub fn final_scene() -> HittableList {
         boxes2.add(sphere);
     }
 
-    let p = BvhNode::new_with_list(&mut boxes2, 0.0, 1.0);
+    let p = Bvh::new_with_list(&boxes2, 0.0, 1.0);
     let ptr = RotateY::new(p, 15.0);
     objects.add(Arc::new(Translate::new(
         ptr,
@@ -823,13 +1033,17 @@ pub fn final_scene() -> HittableList {
     )));
 
     let mut ret_objects = HittableList::new();
-    ret_objects.add(Arc::new(BvhNode::new_with_list(&mut objects, 0., 1.)));
+    ret_objects.add(Arc::new(Bvh::new_with_list(&objects, 0., 1.)));
     ret_objects
 }
 
+// The stars around the solar system
+fn star_field() -> Arc<dyn Environment> {
+    Arc::new(EnvironmentMap::load("pictures/background.jpg", 0.0, 1.0).unwrap())
+}
+
 pub fn solar_system() -> HittableList {
     let mut objects = HittableList::new();
-    let background = Lambertian::new(ImageTexture::new("pictures/background.jpg"));
     let sun = Lambertian::new(ImageTexture::new("pictures/sun.jpg"));
     let mercury = Lambertian::new(ImageTexture::new("pictures/mercury.jpg"));
     let venus = Lambertian::new(ImageTexture::new("pictures/venus.jpg"));
@@ -840,15 +1054,6 @@ pub fn solar_system() -> HittableList {
     let uranus = Lambertian::new(ImageTexture::new("pictures/uranus.jpg"));
     let neptune = Lambertian::new(ImageTexture::new("pictures/neptune.jpg"));
 
-    objects.add(Arc::new(XYRect::new(
-        -600.0,
-        600.0,
-        -600.0,
-        600.0,
-        600.0,
-        background.clone(),
-    )));
-
     objects.add(Arc::new(Sphere::new(
         &Point3::new(0.0, 200.0, 0.0),
         70.0,
@@ -985,7 +1190,7 @@ pub fn solar_system() -> HittableList {
         objects.add(sphere);
     }
 
-    // let p = BvhNode::new_with_list(&mut boxes, 0., 0.);
+    // let p = Bvh::new_with_list(&boxes, 0., 0.);
     // objects.add(Arc::new(p));
 
     // let mut boxes2 = HittableList::new();
@@ -1015,9 +1220,9 @@ pub fn solar_system() -> HittableList {
     //     ));
     //     objects.add(sphere);
     // }
-    // objects.add(Arc::new(BvhNode::new_with_list(&mut boxes2, 0., 0.)));
+    // objects.add(Arc::new(Bvh::new_with_list(&boxes2, 0., 0.)));
 
     let mut ret_objects = HittableList::new();
-    ret_objects.add(Arc::new(BvhNode::new_with_list(&mut objects, 0., 0.)));
+    ret_objects.add(Arc::new(Bvh::new_with_list(&objects, 0., 0.)));
     ret_objects
 }